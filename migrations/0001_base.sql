@@ -0,0 +1,113 @@
+-- tables that predate the migrations, IF NOT EXISTS lets this run against
+-- databases created by hand before the migrations were added
+CREATE TABLE IF NOT EXISTS public.users (
+    users_id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL,
+    created_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    latest_sign_in TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    profile_uri TEXT,
+    cover_uri TEXT,
+    information TEXT
+);
+
+CREATE TABLE IF NOT EXISTS public.verification (
+    verification_id SERIAL PRIMARY KEY,
+    reference INTEGER NOT NULL,
+    code INTEGER NOT NULL,
+    expire TIMESTAMPTZ NOT NULL,
+    verified BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS public.special_front (
+    special_front_id SERIAL PRIMARY KEY,
+    front TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS public.plates (
+    plates_id SERIAL PRIMARY KEY,
+    front_text TEXT NOT NULL,
+    front_number INTEGER NOT NULL,
+    back_number INTEGER NOT NULL,
+    province_id INTEGER NOT NULL,
+    plates_type_id INTEGER NOT NULL,
+    vehicle_type_id INTEGER NOT NULL,
+    special_front_id INTEGER NOT NULL DEFAULT 1,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    total INTEGER NOT NULL DEFAULT 0,
+    plates_uri TEXT,
+    information TEXT,
+    is_selling BOOLEAN NOT NULL DEFAULT TRUE,
+    is_pin BOOLEAN NOT NULL DEFAULT FALSE,
+    is_temporary BOOLEAN NOT NULL DEFAULT FALSE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS plates_users_id_idx ON public.plates(users_id);
+
+CREATE TABLE IF NOT EXISTS public.price_history (
+    price_history_id SERIAL PRIMARY KEY,
+    plates_id INTEGER NOT NULL REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    price INTEGER NOT NULL,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS price_history_plates_id_idx ON public.price_history(plates_id, price_history_id);
+
+CREATE TABLE IF NOT EXISTS public.liked_plates (
+    liked_plates_id SERIAL PRIMARY KEY,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    plates_id INTEGER NOT NULL REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS public.saved_plates (
+    saved_plates_id SERIAL PRIMARY KEY,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    plates_id INTEGER NOT NULL REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS liked_plates_plates_id_idx ON public.liked_plates(plates_id);
+CREATE INDEX IF NOT EXISTS saved_plates_plates_id_idx ON public.saved_plates(plates_id);
+
+CREATE TABLE IF NOT EXISTS public.liked_store (
+    liked_store_id SERIAL PRIMARY KEY,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    store_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS public.saved_store (
+    saved_store_id SERIAL PRIMARY KEY,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    store_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS liked_store_store_id_idx ON public.liked_store(store_id);
+CREATE INDEX IF NOT EXISTS saved_store_store_id_idx ON public.saved_store(store_id);
+
+CREATE TABLE IF NOT EXISTS public.transfer_plates (
+    transfer_plates_id SERIAL PRIMARY KEY,
+    plates_id INTEGER NOT NULL REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    store_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    received BOOLEAN NOT NULL DEFAULT FALSE,
+    received_date TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS public.hashtag (
+    hashtag_id SERIAL PRIMARY KEY,
+    tag TEXT NOT NULL,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS public.plates_hashtag (
+    plates_hashtag_id SERIAL PRIMARY KEY,
+    plates_id INTEGER NOT NULL REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    hashtag_id INTEGER NOT NULL REFERENCES public.hashtag(hashtag_id) ON DELETE CASCADE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,102 @@
+CREATE TABLE IF NOT EXISTS public.pattern (
+    pattern_id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    rule JSONB NOT NULL,
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS public.plates_pattern (
+    plates_pattern_id SERIAL PRIMARY KEY,
+    plates_id INTEGER NOT NULL REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    pattern_id INTEGER NOT NULL REFERENCES public.pattern(pattern_id) ON DELETE CASCADE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (plates_id, pattern_id)
+);
+
+CREATE INDEX IF NOT EXISTS plates_pattern_pattern_id_idx ON public.plates_pattern(pattern_id);
+
+-- the rules previously hard coded in analyze_pattern, names are kept so
+-- clients sending pattern_* to query_pattern keep working
+INSERT INTO public.pattern(name, rule)
+VALUES ('pattern_168', '{"back_number_list": [168]}'),
+    ('pattern_789', '{"back_number_list": [789]}'),
+    ('pattern_289', '{"back_number_list": [289]}'),
+    ('pattern_456', '{"back_number_list": [456]}'),
+    ('pattern_911', '{"back_number_list": [911]}'),
+    ('pattern_718', '{"back_number_list": [718]}'),
+    ('pattern_992', '{"back_number_list": [992]}'),
+    ('pattern_35', '{"back_number_list": [35]}'),
+    ('pattern_488', '{"back_number_list": [488]}'),
+    ('pattern_9', '{"back_number_list": [9]}'),
+    ('pattern_99', '{"back_number_list": [99]}'),
+    ('pattern_999', '{"back_number_list": [999]}'),
+    ('pattern_9999', '{"back_number_list": [9999]}'),
+    ('pattern_7', '{"back_number_list": [7]}'),
+    ('pattern_77', '{"back_number_list": [77]}'),
+    ('pattern_777', '{"back_number_list": [777]}'),
+    ('pattern_7777', '{"back_number_list": [7777]}'),
+    ('pattern_5', '{"back_number_list": [5]}'),
+    ('pattern_55', '{"back_number_list": [55]}'),
+    ('pattern_555', '{"back_number_list": [555]}'),
+    ('pattern_5555', '{"back_number_list": [5555]}'),
+    ('pattern_8', '{"back_number_list": [8]}'),
+    ('pattern_88', '{"back_number_list": [88]}'),
+    ('pattern_888', '{"back_number_list": [888]}'),
+    ('pattern_8888', '{"back_number_list": [8888]}'),
+    ('pattern_1', '{"back_number_list": [1]}'),
+    ('pattern_599', '{"back_number_list": [599]}'),
+    ('pattern_595', '{"back_number_list": [595]}'),
+    ('pattern_959', '{"back_number_list": [959]}'),
+    ('pattern_955', '{"back_number_list": [955]}'),
+    ('pattern_5959', '{"back_number_list": [5959]}'),
+    ('pattern_9595', '{"back_number_list": [9595]}'),
+    ('pattern_5599', '{"back_number_list": [5599]}'),
+    ('pattern_9955', '{"back_number_list": [9955]}'),
+    ('pattern_5995', '{"back_number_list": [5995]}'),
+    ('pattern_9559', '{"back_number_list": [9559]}'),
+    ('pattern_x', '{"back_number_shape": "x"}'),
+    ('pattern_xx', '{"back_number_shape": "xx"}'),
+    ('pattern_xxx', '{"back_number_shape": "xxx"}'),
+    ('pattern_xxxx', '{"back_number_shape": "xxxx"}'),
+    ('pattern_xy', '{"back_number_shape": "xy"}'),
+    ('pattern_xyy', '{"back_number_shape": "xyy"}'),
+    ('pattern_xyyy', '{"back_number_shape": "xyyy"}'),
+    ('pattern_xxyy', '{"back_number_shape": "xxyy"}'),
+    ('pattern_xyxy', '{"back_number_shape": "xyxy"}'),
+    ('pattern_xyyx', '{"back_number_shape": "xyyx"}'),
+    ('pattern_xyx', '{"back_number_shape": "xyx"}'),
+    ('pattern_xyz', '{"back_number_list": [123, 234, 345, 456, 567, 678, 789]}'),
+    ('pattern_zyx', '{"back_number_list": [987, 876, 765, 654, 543, 432, 321]}'),
+    ('pattern_wxyz', '{"back_number_list": [1234, 2345, 3456, 4567, 5678, 6789]}'),
+    ('pattern_zyxw', '{"back_number_list": [9876, 8765, 7654, 6543, 5432, 4321]}'),
+    ('pattern_x00', '{"back_number_shape": "x00"}'),
+    ('pattern_x000', '{"back_number_shape": "x000"}'),
+    ('pattern_x99', '{"back_number_shape": "x99"}'),
+    ('pattern_x999', '{"back_number_shape": "x999"}'),
+    ('pattern_x55', '{"back_number_shape": "x55"}'),
+    ('pattern_x555', '{"back_number_shape": "x555"}'),
+    ('pattern_rakhang', '{"front_text_prefix": "ฆ", "front_number": 0, "vehicle_type_id_list": [1]}'),
+    ('pattern_kob', '{"front_text": "กบ", "front_number": 0, "vehicle_type_id_list": [1]}'),
+    ('pattern_torthan', '{"front_text_prefix": "ฐ", "front_number": 0, "vehicle_type_id_list": [1]}'),
+    ('pattern_korkai_korkai', '{"front_text": "กก", "front_number": 0, "vehicle_type_id_list": [1]}')
+ON CONFLICT (name) DO NOTHING;
+
+-- copy matches from the old pattern_* tables once, afterwards they are no
+-- longer read or written, new rules are back-filled by /publish_pattern
+DO $$
+DECLARE p RECORD;
+BEGIN
+    FOR p IN SELECT pattern_id, name FROM public.pattern LOOP
+        IF to_regclass('public.' || p.name) IS NOT NULL THEN
+            EXECUTE format(
+                'INSERT INTO public.plates_pattern(plates_id, pattern_id, add_date) SELECT plates_id, %s, MIN(add_date) FROM public.%I GROUP BY plates_id ON CONFLICT (plates_id, pattern_id) DO NOTHING',
+                p.pattern_id,
+                p.name
+            );
+        END IF;
+    END LOOP;
+END $$;
+
+-- pattern admin endpoints are limited to these users
+ALTER TABLE public.users ADD COLUMN IF NOT EXISTS is_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,15 @@
+ALTER TABLE public.transfer_plates ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'pending';
+ALTER TABLE public.transfer_plates ADD COLUMN IF NOT EXISTS expire_date TIMESTAMPTZ NOT NULL DEFAULT NOW() + INTERVAL '7 days';
+
+UPDATE public.transfer_plates SET status = 'accepted' WHERE received IS TRUE;
+
+CREATE TABLE IF NOT EXISTS public.transfer_plates_log (
+    transfer_plates_log_id SERIAL PRIMARY KEY,
+    transfer_plates_id INTEGER NOT NULL REFERENCES public.transfer_plates(transfer_plates_id) ON DELETE CASCADE,
+    users_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- at most one pending transfer per plates
+CREATE UNIQUE INDEX IF NOT EXISTS transfer_plates_pending_idx ON public.transfer_plates(plates_id) WHERE status = 'pending';
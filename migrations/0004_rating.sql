@@ -0,0 +1,41 @@
+CREATE TABLE IF NOT EXISTS public.rating (
+    rating_id SERIAL PRIMARY KEY,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    store_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    score INTEGER NOT NULL,
+    review TEXT NOT NULL DEFAULT '',
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE public.rating ALTER COLUMN score TYPE INTEGER USING ROUND(score)::INTEGER;
+ALTER TABLE public.rating ADD COLUMN IF NOT EXISTS edit_date TIMESTAMPTZ;
+ALTER TABLE public.rating ADD COLUMN IF NOT EXISTS reply TEXT;
+ALTER TABLE public.rating ADD COLUMN IF NOT EXISTS reply_date TIMESTAMPTZ;
+
+-- legacy rows are cleaned so the constraints below can be added, scores are
+-- clamped into range, self ratings dropped and only the latest rating of a
+-- store by the same users kept
+UPDATE public.rating SET score = LEAST(GREATEST(score, 1), 5) WHERE score NOT BETWEEN 1 AND 5;
+DELETE FROM public.rating WHERE users_id = store_id;
+DELETE FROM public.rating AS duplicate USING public.rating
+WHERE duplicate.users_id = rating.users_id
+    AND duplicate.store_id = rating.store_id
+    AND duplicate.rating_id < rating.rating_id;
+
+DO $$ BEGIN
+    ALTER TABLE public.rating ADD CONSTRAINT rating_score_check CHECK (score BETWEEN 1 AND 5);
+EXCEPTION
+    WHEN duplicate_object OR duplicate_table THEN NULL;
+END $$;
+DO $$ BEGIN
+    ALTER TABLE public.rating ADD CONSTRAINT rating_not_self_check CHECK (users_id <> store_id);
+EXCEPTION
+    WHEN duplicate_object OR duplicate_table THEN NULL;
+END $$;
+DO $$ BEGIN
+    ALTER TABLE public.rating ADD CONSTRAINT rating_users_id_store_id_key UNIQUE (users_id, store_id);
+EXCEPTION
+    WHEN duplicate_object OR duplicate_table THEN NULL;
+END $$;
+
+CREATE INDEX IF NOT EXISTS rating_store_id_idx ON public.rating(store_id);
@@ -0,0 +1,30 @@
+-- legacy duplicate tags are merged into the oldest row before the unique
+-- constraint, a plates carrying several of them keeps one plates_hashtag
+DELETE FROM public.plates_hashtag AS duplicate USING public.hashtag, public.hashtag AS kept, public.plates_hashtag
+WHERE duplicate.hashtag_id = hashtag.hashtag_id
+    AND kept.tag = hashtag.tag
+    AND kept.hashtag_id < hashtag.hashtag_id
+    AND plates_hashtag.plates_id = duplicate.plates_id
+    AND plates_hashtag.hashtag_id = kept.hashtag_id;
+
+UPDATE public.plates_hashtag SET hashtag_id = kept.kept_id
+FROM (
+        SELECT hashtag_id, MIN(hashtag_id) OVER (PARTITION BY tag) AS kept_id
+        FROM public.hashtag
+    ) AS kept
+WHERE plates_hashtag.hashtag_id = kept.hashtag_id
+    AND kept.hashtag_id <> kept.kept_id;
+
+DELETE FROM public.hashtag AS duplicate USING public.hashtag
+WHERE duplicate.tag = hashtag.tag
+    AND duplicate.hashtag_id > hashtag.hashtag_id;
+
+DO $$ BEGIN
+    ALTER TABLE public.hashtag ADD CONSTRAINT hashtag_tag_key UNIQUE (tag);
+EXCEPTION
+    WHEN duplicate_object OR duplicate_table THEN NULL;
+END $$;
+
+CREATE INDEX IF NOT EXISTS hashtag_tag_pattern_idx ON public.hashtag(tag text_pattern_ops);
+CREATE INDEX IF NOT EXISTS plates_hashtag_hashtag_id_idx ON public.plates_hashtag(hashtag_id, add_date);
+CREATE INDEX IF NOT EXISTS plates_hashtag_plates_id_idx ON public.plates_hashtag(plates_id);
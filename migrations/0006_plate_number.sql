@@ -0,0 +1,31 @@
+-- recompute plates.total on the server with the plate_number consonant table,
+-- every consonant is translated to its value and all digits are summed
+UPDATE public.plates
+SET total = (
+        SELECT COALESCE(SUM(digit::INTEGER), 0)
+        FROM REGEXP_SPLIT_TO_TABLE(
+                REGEXP_REPLACE(
+                    TRANSLATE(
+                        plates.front_text,
+                        'กดถทภฤขฃงชบปฆตฑฒคฅญธรษฉฌฎณนมหฬฮจลวอซศสผฝพฟยฏฐ',
+                        '111111222222333344444455555555566667778888899'
+                    ),
+                    '[^0-9]',
+                    '',
+                    'g'
+                ) || plates.front_number::TEXT || plates.back_number::TEXT,
+                ''
+            ) AS digit
+    );
+
+CREATE INDEX IF NOT EXISTS plates_total_idx ON public.plates(total);
+
+-- back number shape search compares prefixes, lengths and single digits of
+-- the back number as text
+CREATE INDEX IF NOT EXISTS plates_back_number_text_idx ON public.plates((back_number::TEXT) text_pattern_ops);
+CREATE INDEX IF NOT EXISTS plates_back_number_length_idx ON public.plates(LENGTH(back_number::TEXT));
+
+-- fuzzy front text search ranks with pg_trgm similarity
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS plates_front_text_trgm_idx ON public.plates USING GIN (front_text gin_trgm_ops);
@@ -0,0 +1,52 @@
+-- one row per priced plates holding its current and previous price and
+-- reaction counts, kept in step by the handlers that write price_history,
+-- liked_plates and saved_plates so listings no longer rank price_history
+CREATE TABLE IF NOT EXISTS public.plates_stats (
+    plates_id INTEGER PRIMARY KEY REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    price INTEGER NOT NULL,
+    previous_price INTEGER,
+    liked_plates_id_count BIGINT NOT NULL DEFAULT 0,
+    saved_plates_id_count BIGINT NOT NULL DEFAULT 0,
+    reacts_count BIGINT GENERATED ALWAYS AS (liked_plates_id_count + saved_plates_id_count) STORED
+);
+
+INSERT INTO public.plates_stats(
+        plates_id,
+        price,
+        previous_price,
+        liked_plates_id_count,
+        saved_plates_id_count
+    )
+SELECT ranked.plates_id,
+    ranked.price,
+    ranked.previous_price,
+    (
+        SELECT COUNT(*)
+        FROM public.liked_plates
+        WHERE liked_plates.plates_id = ranked.plates_id
+    ),
+    (
+        SELECT COUNT(*)
+        FROM public.saved_plates
+        WHERE saved_plates.plates_id = ranked.plates_id
+    )
+FROM (
+        SELECT price_history.plates_id,
+            price_history.price,
+            LEAD(price_history.price) OVER (
+                PARTITION BY price_history.plates_id
+                ORDER BY price_history.price_history_id DESC
+            ) AS previous_price,
+            ROW_NUMBER() OVER (
+                PARTITION BY price_history.plates_id
+                ORDER BY price_history.price_history_id DESC
+            ) AS rownumber
+        FROM public.price_history
+    ) AS ranked
+WHERE ranked.rownumber = 1 ON CONFLICT (plates_id) DO NOTHING;
+
+CREATE INDEX IF NOT EXISTS plates_stats_price_idx ON public.plates_stats(price, plates_id);
+CREATE INDEX IF NOT EXISTS plates_stats_reacts_count_idx ON public.plates_stats(reacts_count, plates_id);
+CREATE INDEX IF NOT EXISTS plates_add_date_idx ON public.plates(add_date, plates_id);
+CREATE INDEX IF NOT EXISTS liked_plates_plates_id_idx ON public.liked_plates(plates_id);
+CREATE INDEX IF NOT EXISTS saved_plates_plates_id_idx ON public.saved_plates(plates_id);
@@ -0,0 +1,44 @@
+-- plates and their hashtags are deduplicated by unique constraints instead of
+-- the unique_text strings the handlers used to build and look up
+
+-- plates of different owners cannot be merged, so legacy duplicates of a
+-- number are flagged and left out of the unique index instead of blocking the
+-- migration, the oldest plates of each number stays the indexed one
+ALTER TABLE public.plates ADD COLUMN IF NOT EXISTS number_conflict BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE public.plates SET number_conflict = TRUE
+FROM public.plates AS kept
+WHERE kept.province_id = plates.province_id
+    AND kept.vehicle_type_id = plates.vehicle_type_id
+    AND kept.front_text = plates.front_text
+    AND kept.front_number = plates.front_number
+    AND kept.back_number = plates.back_number
+    AND kept.plates_id < plates.plates_id
+    AND plates.number_conflict IS FALSE;
+
+DO $$
+DECLARE
+    conflict_count BIGINT;
+BEGIN
+    SELECT COUNT(*) INTO conflict_count FROM public.plates WHERE number_conflict IS TRUE;
+    IF conflict_count > 0 THEN
+        RAISE WARNING '% plates duplicate the number of an older plates, see plates.number_conflict', conflict_count;
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS plates_number_key ON public.plates(province_id, vehicle_type_id, front_text, front_number, back_number)
+WHERE number_conflict IS FALSE;
+
+DELETE FROM public.plates_hashtag AS duplicate USING public.plates_hashtag
+WHERE duplicate.plates_id = plates_hashtag.plates_id
+    AND duplicate.hashtag_id = plates_hashtag.hashtag_id
+    AND duplicate.plates_hashtag_id > plates_hashtag.plates_hashtag_id;
+
+DO $$ BEGIN
+    ALTER TABLE public.plates_hashtag ADD CONSTRAINT plates_hashtag_plates_id_hashtag_id_key UNIQUE (plates_id, hashtag_id);
+EXCEPTION
+    WHEN duplicate_object OR duplicate_table THEN NULL;
+END $$;
+
+ALTER TABLE public.plates DROP COLUMN IF EXISTS unique_text;
+ALTER TABLE public.plates_hashtag DROP COLUMN IF EXISTS unique_text;
@@ -0,0 +1,14 @@
+-- one row per signed in device, jti is the id of the only refresh token of
+-- the session that can still be renewed
+CREATE TABLE IF NOT EXISTS public.sessions (
+    sessions_id SERIAL PRIMARY KEY,
+    users_id INTEGER NOT NULL REFERENCES public.users(users_id) ON DELETE CASCADE,
+    jti TEXT NOT NULL,
+    user_agent TEXT,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expire_date TIMESTAMPTZ NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS sessions_users_id_idx ON public.sessions(users_id);
@@ -0,0 +1,8 @@
+-- attempt counters keyed by limit name and value, e.g. sign_in_email:a@b.c,
+-- attempts count within the window starting at window_start
+CREATE TABLE IF NOT EXISTS public.rate_limit (
+    key TEXT PRIMARY KEY,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    window_start TIMESTAMPTZ NOT NULL,
+    blocked_until TIMESTAMPTZ
+);
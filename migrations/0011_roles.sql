@@ -0,0 +1,21 @@
+-- roles replace is_admin, ordered user < verified_seller < moderator < admin
+ALTER TABLE public.users ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'user';
+
+DO $$ BEGIN
+    ALTER TABLE public.users ADD CONSTRAINT users_role_check CHECK (role IN ('user', 'verified_seller', 'moderator', 'admin'));
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$ BEGIN
+    IF EXISTS (
+        SELECT 1
+        FROM information_schema.columns
+        WHERE table_schema = 'public'
+            AND table_name = 'users'
+            AND column_name = 'is_admin'
+    ) THEN
+        UPDATE public.users SET role = 'admin' WHERE is_admin IS TRUE;
+        ALTER TABLE public.users DROP COLUMN is_admin;
+    END IF;
+END $$;
@@ -0,0 +1,22 @@
+-- apps allowed to call the api, e.g. ios, android and web, each can hold
+-- several active keys so a key can be rotated without breaking old builds
+CREATE TABLE IF NOT EXISTS public.api_clients (
+    api_clients_id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- only the blake3 hex digest of a key is stored, prefix is its first
+-- characters so a key can be recognised in listings
+CREATE TABLE IF NOT EXISTS public.api_keys (
+    api_keys_id SERIAL PRIMARY KEY,
+    api_clients_id INTEGER NOT NULL REFERENCES public.api_clients(api_clients_id) ON DELETE CASCADE,
+    key_hash TEXT NOT NULL UNIQUE,
+    prefix TEXT NOT NULL,
+    add_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_date TIMESTAMPTZ,
+    request_count BIGINT NOT NULL DEFAULT 0,
+    last_used TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_api_clients_id_idx ON public.api_keys(api_clients_id);
@@ -0,0 +1,11 @@
+-- an email change is a verification row owned by users_id for the new email,
+-- once swapped it keeps previous_email and the blake3 digest of the token in
+-- the cancel link sent to the previous email
+ALTER TABLE public.verification
+    ADD COLUMN IF NOT EXISTS users_id INTEGER REFERENCES public.users(users_id) ON DELETE CASCADE,
+    ADD COLUMN IF NOT EXISTS email TEXT,
+    ADD COLUMN IF NOT EXISTS previous_email TEXT,
+    ADD COLUMN IF NOT EXISTS cancel_token TEXT,
+    ADD COLUMN IF NOT EXISTS cancel_expire TIMESTAMPTZ;
+
+CREATE UNIQUE INDEX IF NOT EXISTS verification_cancel_token_key ON public.verification(cancel_token);
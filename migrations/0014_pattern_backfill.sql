@@ -0,0 +1,18 @@
+-- published and re-enabled patterns are back-filled by a background job in
+-- batches after backfill_after, matches collect in plates_pattern_staging and
+-- replace the live plates_pattern rows only once the scan is complete
+ALTER TABLE public.pattern
+    ADD COLUMN IF NOT EXISTS backfill_status TEXT NOT NULL DEFAULT 'done',
+    ADD COLUMN IF NOT EXISTS backfill_after INTEGER NOT NULL DEFAULT 0;
+
+DO $$ BEGIN
+    ALTER TABLE public.pattern ADD CONSTRAINT pattern_backfill_status_check CHECK (backfill_status IN ('pending', 'done'));
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS public.plates_pattern_staging (
+    pattern_id INTEGER NOT NULL REFERENCES public.pattern(pattern_id) ON DELETE CASCADE,
+    plates_id INTEGER NOT NULL REFERENCES public.plates(plates_id) ON DELETE CASCADE,
+    PRIMARY KEY (pattern_id, plates_id)
+);
@@ -0,0 +1,42 @@
+-- tags stored before normalize_tag existed are rewritten the same way, tags
+-- that become equal are merged into the oldest one and their plates repointed
+CREATE TEMPORARY TABLE hashtag_normalized AS
+SELECT hashtag_id, tag, MIN(hashtag_id) OVER (PARTITION BY tag) AS keep_id
+FROM (
+    SELECT hashtag_id,
+        REGEXP_REPLACE(
+            REGEXP_REPLACE(
+                LOWER(REPLACE(
+                    REGEXP_REPLACE(LTRIM(REGEXP_REPLACE(tag, '^\s+', ''), '#'), U&'[\200B\200C\200D\FEFF]', '', 'g'),
+                    U&'\0E4D\0E32', U&'\0E33'
+                )),
+                '^\s+|\s+$', '', 'g'
+            ),
+            '\s+', '_', 'g'
+        ) AS tag
+    FROM public.hashtag
+) AS normalized;
+
+-- a plates tagged with several spellings keeps a single row
+DELETE FROM public.plates_hashtag
+USING hashtag_normalized
+WHERE (plates_hashtag.hashtag_id = hashtag_normalized.hashtag_id AND EXISTS (
+    SELECT 1 FROM public.plates_hashtag AS other
+    JOIN hashtag_normalized AS other_normalized ON other_normalized.hashtag_id = other.hashtag_id
+    WHERE (other.plates_id = plates_hashtag.plates_id AND other_normalized.keep_id = hashtag_normalized.keep_id AND other.hashtag_id < plates_hashtag.hashtag_id)
+));
+
+UPDATE public.plates_hashtag SET hashtag_id = hashtag_normalized.keep_id
+FROM hashtag_normalized
+WHERE (plates_hashtag.hashtag_id = hashtag_normalized.hashtag_id AND hashtag_normalized.hashtag_id <> hashtag_normalized.keep_id);
+
+-- tags that normalize to nothing cannot be typed anymore
+DELETE FROM public.hashtag
+USING hashtag_normalized
+WHERE (hashtag.hashtag_id = hashtag_normalized.hashtag_id AND (hashtag_normalized.hashtag_id <> hashtag_normalized.keep_id OR hashtag_normalized.tag = ''));
+
+UPDATE public.hashtag SET tag = hashtag_normalized.tag
+FROM hashtag_normalized
+WHERE (hashtag.hashtag_id = hashtag_normalized.hashtag_id AND hashtag.tag <> hashtag_normalized.tag);
+
+DROP TABLE hashtag_normalized;
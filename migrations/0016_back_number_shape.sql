@@ -0,0 +1,24 @@
+-- the digit signature of the back number, each digit becomes a letter in order
+-- of first appearance so 1212 is abab and 155 is abb, must match
+-- plate_number::shape_signature
+CREATE OR REPLACE FUNCTION public.back_number_shape(back_number INTEGER) RETURNS TEXT
+LANGUAGE SQL IMMUTABLE PARALLEL SAFE AS $$
+    SELECT STRING_AGG(
+            CHR(97 + (
+                SELECT COUNT(DISTINCT SUBSTR(digits, j, 1))::INTEGER
+                FROM GENERATE_SERIES(1, STRPOS(digits, SUBSTR(digits, i, 1)) - 1) AS j
+            )),
+            '' ORDER BY i
+        )
+    FROM (SELECT ABS(back_number)::TEXT AS digits) AS number,
+        GENERATE_SERIES(1, LENGTH(digits)) AS i
+$$;
+
+-- shape search looks plates up by signature instead of comparing single
+-- digits of every back number
+ALTER TABLE public.plates
+    ADD COLUMN IF NOT EXISTS back_number_shape TEXT GENERATED ALWAYS AS (public.back_number_shape(back_number)) STORED;
+
+CREATE INDEX IF NOT EXISTS plates_back_number_shape_idx ON public.plates(back_number_shape);
+
+DROP INDEX IF EXISTS public.plates_back_number_length_idx;
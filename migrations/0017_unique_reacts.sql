@@ -0,0 +1,36 @@
+-- a plates is liked or saved at most once per users, repeated requests are
+-- ignored by ON CONFLICT in the handlers
+DELETE FROM public.liked_plates AS duplicate USING public.liked_plates
+WHERE duplicate.users_id = liked_plates.users_id
+    AND duplicate.plates_id = liked_plates.plates_id
+    AND duplicate.liked_plates_id > liked_plates.liked_plates_id;
+
+DO $$ BEGIN
+    ALTER TABLE public.liked_plates ADD CONSTRAINT liked_plates_users_id_plates_id_key UNIQUE (users_id, plates_id);
+EXCEPTION
+    WHEN duplicate_object OR duplicate_table THEN NULL;
+END $$;
+
+DELETE FROM public.saved_plates AS duplicate USING public.saved_plates
+WHERE duplicate.users_id = saved_plates.users_id
+    AND duplicate.plates_id = saved_plates.plates_id
+    AND duplicate.saved_plates_id > saved_plates.saved_plates_id;
+
+DO $$ BEGIN
+    ALTER TABLE public.saved_plates ADD CONSTRAINT saved_plates_users_id_plates_id_key UNIQUE (users_id, plates_id);
+EXCEPTION
+    WHEN duplicate_object OR duplicate_table THEN NULL;
+END $$;
+
+-- counters bumped by the duplicates are recounted
+UPDATE public.plates_stats
+SET liked_plates_id_count = (
+        SELECT COUNT(*)
+        FROM public.liked_plates
+        WHERE liked_plates.plates_id = plates_stats.plates_id
+    ),
+    saved_plates_id_count = (
+        SELECT COUNT(*)
+        FROM public.saved_plates
+        WHERE saved_plates.plates_id = plates_stats.plates_id
+    );
@@ -0,0 +1,103 @@
+-- listing latency at 100k plates, run against a scratch database migrated
+-- from migrations/ and seeded with 2000 stores, 150k price_history rows,
+-- 300k liked_plates and 200k saved_plates rows, on postgres 15
+--
+--                       latest_price CTE    plates_stats
+-- addDate, 30 rows      2.8 to 3.2 s        0.6 to 1.3 ms
+-- priceLowToHigh        2.8 to 3.0 s        0.9 to 2.3 ms
+INSERT INTO public.users(name)
+SELECT 'store ' || g
+FROM GENERATE_SERIES(1, 2000) AS g;
+
+INSERT INTO public.plates(
+        front_text,
+        province_id,
+        plates_type_id,
+        users_id,
+        total,
+        add_date,
+        front_number,
+        back_number
+    )
+SELECT (ARRAY ['กข', 'ขค', 'ฆง', 'ชซ', 'กก']) [1 + g % 5],
+    1 + g % 77,
+    1 + g % 5,
+    1 + g % 2000,
+    g % 60,
+    NOW() - (g || ' minutes')::INTERVAL,
+    g % 10,
+    1 + g % 9999
+FROM GENERATE_SERIES(1, 100000) AS g;
+
+INSERT INTO public.price_history(plates_id, price, add_date)
+SELECT plates_id,
+    10000 + (plates_id * 37) % 500000,
+    add_date
+FROM public.plates;
+
+INSERT INTO public.price_history(plates_id, price, add_date)
+SELECT plates_id,
+    9000 + (plates_id * 31) % 500000,
+    add_date + INTERVAL '1 day'
+FROM public.plates
+WHERE plates_id % 2 = 0;
+
+INSERT INTO public.liked_plates(users_id, plates_id, add_date)
+SELECT 1 + g % 2000,
+    1 + (g * 7) % 100000,
+    NOW()
+FROM GENERATE_SERIES(1, 300000) AS g;
+
+INSERT INTO public.saved_plates(users_id, plates_id, add_date)
+SELECT 1 + g % 2000,
+    1 + (g * 13) % 100000,
+    NOW()
+FROM GENERATE_SERIES(1, 200000) AS g;
+
+ANALYZE;
+
+-- the listing as PlatesQuery builds it for sort_by addDate
+PREPARE listing(INTEGER, INTEGER, INTEGER, INTEGER, BIGINT, BIGINT) AS
+SELECT plates.plates_id,
+    plates.front_text,
+    plates.plates_type_id,
+    plates.plates_uri,
+    plates.total,
+    plates.add_date::TEXT,
+    plates.front_number,
+    plates.back_number,
+    plates.vehicle_type_id,
+    plates.users_id,
+    plates.special_front_id,
+    plates.province_id,
+    plates.information,
+    latest_price.price,
+    users.name,
+    users.profile_uri,
+    liked_plates.liked_plates_id,
+    saved_plates.saved_plates_id,
+    liked_store.liked_store_id,
+    saved_store.saved_store_id,
+    latest_price.liked_plates_id_count,
+    latest_price.saved_plates_id_count,
+    latest_price.reacts_count,
+    1::BIGINT AS rownumber,
+    COALESCE(latest_price.price < latest_price.previous_price, FALSE) AS price_dropped
+FROM public.plates_stats AS latest_price
+    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
+    INNER JOIN public.users ON users.users_id = plates.users_id
+    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
+    AND liked_plates.users_id = $1
+    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
+    AND saved_plates.users_id = $2
+    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
+    AND liked_store.users_id = $3
+    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
+    AND saved_store.users_id = $4
+WHERE plates.is_selling IS TRUE
+    AND plates.is_temporary IS NOT TRUE
+ORDER BY plates.add_date DESC,
+    plates.plates_id DESC
+LIMIT $5 OFFSET $6;
+
+EXPLAIN (ANALYZE, SUMMARY) EXECUTE listing(1, 1, 1, 1, 30, 0);
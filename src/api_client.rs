@@ -0,0 +1,319 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const PREFIX_CHARS: usize = 8;
+// a revoked key is refused by every server within KEY_CACHE_TTL
+const KEY_CACHE_TTL: Duration = Duration::from_secs(60);
+// unknown keys are remembered briefly so retrying with a bad key does not
+// reach the database on every request, the oldest are dropped past capacity
+const UNKNOWN_KEY_TTL: Duration = Duration::from_secs(10);
+const UNKNOWN_KEY_CAPACITY: usize = 10_000;
+const USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+struct CachedKey {
+    api_keys_id: i32,
+    api_clients_id: i32,
+    fetched: Instant,
+}
+
+struct Usage {
+    request_count: i64,
+    last_used: DateTime<Utc>,
+}
+
+// active keys by key_hash, unknown key_hash with when they were looked up and
+// the requests counted since the last flush by api_keys_id, shared by every
+// request through AppState
+#[derive(Default)]
+pub struct ApiKeys {
+    keys: Mutex<HashMap<String, CachedKey>>,
+    unknown: Mutex<HashMap<String, Instant>>,
+    usage: Mutex<HashMap<i32, Usage>>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ApiKeyUsage {
+    pub name: String,
+    pub api_keys_id: Option<i32>,
+    pub prefix: Option<String>,
+    pub add_date: Option<DateTime<Utc>>,
+    pub revoked_date: Option<DateTime<Utc>>,
+    pub request_count: Option<i64>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+pub fn hash_key(key: &str) -> String {
+    blake3::hash(key.as_bytes()).to_hex().to_string()
+}
+
+fn generate_key() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().fold(String::new(), |mut key, byte| {
+        let _ = write!(key, "{byte:02x}");
+        key
+    })
+}
+
+fn is_unknown(api_keys: &ApiKeys, key_hash: &str) -> bool {
+    api_keys
+        .unknown
+        .lock()
+        .unwrap()
+        .get(key_hash)
+        .is_some_and(|fetched| fetched.elapsed() < UNKNOWN_KEY_TTL)
+}
+
+fn remember_unknown(api_keys: &ApiKeys, key_hash: String) {
+    let mut unknown = api_keys.unknown.lock().unwrap();
+    if unknown.len() >= UNKNOWN_KEY_CAPACITY {
+        unknown.retain(|_, fetched| fetched.elapsed() < UNKNOWN_KEY_TTL);
+        let oldest = unknown
+            .iter()
+            .min_by_key(|(_, fetched)| **fetched)
+            .map(|(key_hash, _)| key_hash.clone());
+        if let Some(oldest) = oldest.filter(|_| unknown.len() >= UNKNOWN_KEY_CAPACITY) {
+            unknown.remove(&oldest);
+        }
+    }
+    unknown.insert(key_hash, Instant::now());
+}
+
+// looks the key up without writing and counts the request in memory, None
+// when the key is unknown or revoked
+pub async fn use_key(
+    pool: &Pool<Postgres>,
+    api_keys: &ApiKeys,
+    key: &str,
+) -> Result<Option<i32>, sqlx::Error> {
+    let key_hash = hash_key(key);
+    let cached = api_keys
+        .keys
+        .lock()
+        .unwrap()
+        .get(&key_hash)
+        .filter(|cached| cached.fetched.elapsed() < KEY_CACHE_TTL)
+        .map(|cached| (cached.api_keys_id, cached.api_clients_id));
+    let (api_keys_id, api_clients_id) = match cached {
+        Some(ok) => ok,
+        None if is_unknown(api_keys, &key_hash) => return Ok(None),
+        None => {
+            let fetch: Option<(i32, i32)> = sqlx::query_as("SELECT api_keys_id, api_clients_id FROM public.api_keys WHERE (key_hash = $1 AND revoked_date IS NULL)")
+                .bind(&key_hash)
+                .fetch_optional(pool)
+                .await?;
+            let mut keys = api_keys.keys.lock().unwrap();
+            match fetch {
+                Some((api_keys_id, api_clients_id)) => {
+                    keys.insert(
+                        key_hash,
+                        CachedKey {
+                            api_keys_id,
+                            api_clients_id,
+                            fetched: Instant::now(),
+                        },
+                    );
+                    (api_keys_id, api_clients_id)
+                }
+                None => {
+                    keys.remove(&key_hash);
+                    remember_unknown(api_keys, key_hash);
+                    return Ok(None);
+                }
+            }
+        }
+    };
+    let now = Utc::now();
+    api_keys
+        .usage
+        .lock()
+        .unwrap()
+        .entry(api_keys_id)
+        .and_modify(|usage| {
+            usage.request_count += 1;
+            usage.last_used = now;
+        })
+        .or_insert(Usage {
+            request_count: 1,
+            last_used: now,
+        });
+    Ok(Some(api_clients_id))
+}
+
+// adds the counted requests to api_keys in one statement, counts that could
+// not be written are kept for the next flush
+pub async fn flush_usage(pool: &Pool<Postgres>, api_keys: &ApiKeys) -> Result<(), sqlx::Error> {
+    let usage = std::mem::take(&mut *api_keys.usage.lock().unwrap());
+    if usage.is_empty() {
+        return Ok(());
+    }
+    let mut api_keys_id_list = Vec::with_capacity(usage.len());
+    let mut request_count_list = Vec::with_capacity(usage.len());
+    let mut last_used_list = Vec::with_capacity(usage.len());
+    for (api_keys_id, counted) in &usage {
+        api_keys_id_list.push(*api_keys_id);
+        request_count_list.push(counted.request_count);
+        last_used_list.push(counted.last_used);
+    }
+    let update = sqlx::query("UPDATE public.api_keys SET request_count = api_keys.request_count + counted.request_count, last_used = GREATEST(api_keys.last_used, counted.last_used) FROM UNNEST($1::INTEGER[], $2::BIGINT[], $3::TIMESTAMPTZ[]) AS counted(api_keys_id, request_count, last_used) WHERE api_keys.api_keys_id = counted.api_keys_id")
+        .bind(api_keys_id_list)
+        .bind(request_count_list)
+        .bind(last_used_list)
+        .execute(pool)
+        .await;
+    if let Err(err) = update {
+        let mut pending = api_keys.usage.lock().unwrap();
+        for (api_keys_id, counted) in usage {
+            pending
+                .entry(api_keys_id)
+                .and_modify(|usage| {
+                    usage.request_count += counted.request_count;
+                    usage.last_used = usage.last_used.max(counted.last_used);
+                })
+                .or_insert(counted);
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+// spawned once at startup, main flushes once more after shutdown
+pub async fn run_usage_flush(pool: Pool<Postgres>, api_keys: Arc<ApiKeys>) {
+    loop {
+        tokio::time::sleep(USAGE_FLUSH_INTERVAL).await;
+        if let Err(err) = flush_usage(&pool, &api_keys).await {
+            tracing::warn!("api key usage flush failed: {err}");
+        }
+    }
+}
+
+// the key is only ever returned here, it cannot be read back later
+async fn issue_key(pool: &Pool<Postgres>, name: &str) -> Result<Option<String>, sqlx::Error> {
+    let key = generate_key();
+    let insert: Option<(i32,)> = sqlx::query_as("INSERT INTO public.api_keys(api_clients_id, key_hash, prefix, add_date) SELECT api_clients_id, $2, $3, $4 FROM public.api_clients WHERE name = $1 RETURNING api_keys_id")
+        .bind(name)
+        .bind(hash_key(&key))
+        .bind(&key[..PREFIX_CHARS])
+        .bind(Utc::now())
+        .fetch_optional(pool)
+        .await?;
+    Ok(insert.map(|_| key))
+}
+
+pub async fn create_client(
+    pool: &Pool<Postgres>,
+    name: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let insert: Option<(i32,)> = sqlx::query_as("INSERT INTO public.api_clients(name, add_date) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING RETURNING api_clients_id")
+        .bind(name)
+        .bind(Utc::now())
+        .fetch_optional(pool)
+        .await?;
+    match insert {
+        Some(_) => issue_key(pool, name).await,
+        None => Ok(None),
+    }
+}
+
+// adds a key next to the active ones, the old keys stay valid until revoked
+// so released builds keep working while the new key rolls out
+pub async fn rotate_key(pool: &Pool<Postgres>, name: &str) -> Result<Option<String>, sqlx::Error> {
+    issue_key(pool, name).await
+}
+
+pub async fn revoke_key(pool: &Pool<Postgres>, api_keys_id: i32) -> Result<bool, sqlx::Error> {
+    let update = sqlx::query("UPDATE public.api_keys SET revoked_date = $2 WHERE (api_keys_id = $1 AND revoked_date IS NULL)")
+        .bind(api_keys_id)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+    Ok(update.rows_affected() > 0)
+}
+
+pub async fn list_usage(pool: &Pool<Postgres>) -> Result<Vec<ApiKeyUsage>, sqlx::Error> {
+    sqlx::query_as("SELECT api_clients.name, api_keys.api_keys_id, api_keys.prefix, api_keys.add_date, api_keys.revoked_date, api_keys.request_count, api_keys.last_used FROM public.api_clients LEFT JOIN public.api_keys ON api_keys.api_clients_id = api_clients.api_clients_id ORDER BY api_clients.name ASC, api_keys.api_keys_id ASC")
+        .fetch_all(pool)
+        .await
+}
+
+const USAGE: &str = "usage: client create <name> | client rotate <name> | client revoke <api_keys_id> | client list";
+
+// admin subcommand, `client <command>` on the server binary
+pub async fn run_command(pool: &Pool<Postgres>, args: &[String]) -> Result<(), String> {
+    let command = args.first().map(String::as_str);
+    let argument = args.get(1).map(String::as_str);
+    match (command, argument) {
+        (Some("create"), Some(name)) => match create_client(pool, name).await {
+            Ok(Some(key)) => {
+                println!("created client {name}, api key (shown once): {key}");
+                Ok(())
+            }
+            Ok(None) => Err(format!("client {name} already exists")),
+            Err(err) => Err(err.to_string()),
+        },
+        (Some("rotate"), Some(name)) => match rotate_key(pool, name).await {
+            Ok(Some(key)) => {
+                println!("new api key for {name} (shown once): {key}");
+                println!("older keys stay active until revoked");
+                Ok(())
+            }
+            Ok(None) => Err(format!("client {name} does not exist")),
+            Err(err) => Err(err.to_string()),
+        },
+        (Some("revoke"), Some(api_keys_id)) => {
+            let api_keys_id = api_keys_id
+                .parse::<i32>()
+                .map_err(|_| format!("{api_keys_id} is not an api_keys_id"))?;
+            match revoke_key(pool, api_keys_id).await {
+                Ok(true) => {
+                    println!(
+                        "revoked api key {api_keys_id}, running servers refuse it within a minute"
+                    );
+                    Ok(())
+                }
+                Ok(false) => Err(format!(
+                    "api key {api_keys_id} does not exist or is revoked"
+                )),
+                Err(err) => Err(err.to_string()),
+            }
+        }
+        (Some("list"), None) => match list_usage(pool).await {
+            Ok(ok) => {
+                for usage in ok {
+                    match usage.api_keys_id {
+                        Some(api_keys_id) => println!(
+                            "{}\t{}\t{}...\tadded {}\t{}\t{} requests\tlast used {}",
+                            usage.name,
+                            api_keys_id,
+                            usage.prefix.unwrap_or_default(),
+                            usage
+                                .add_date
+                                .map(|date| date.to_rfc3339())
+                                .unwrap_or_default(),
+                            match usage.revoked_date {
+                                Some(date) => format!("revoked {}", date.to_rfc3339()),
+                                None => "active".to_string(),
+                            },
+                            usage.request_count.unwrap_or_default(),
+                            usage
+                                .last_used
+                                .map(|date| date.to_rfc3339())
+                                .unwrap_or("never".to_string()),
+                        ),
+                        None => println!("{}\tno keys", usage.name),
+                    }
+                }
+                Ok(())
+            }
+            Err(err) => Err(err.to_string()),
+        },
+        _ => Err(USAGE.to_string()),
+    }
+}
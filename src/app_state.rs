@@ -1,8 +1,12 @@
+use crate::{api_client::ApiKeys, config::Config};
 use aws_sdk_s3::Client;
 use sqlx::{Pool, Postgres};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: Pool<Postgres>,
     pub client: Client,
+    pub config: Arc<Config>,
+    pub api_keys: Arc<ApiKeys>,
 }
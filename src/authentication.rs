@@ -1,17 +1,28 @@
 use crate::{
     app_state::AppState,
-    constants::{
-        ACCESS_TOKEN_KEY, EXP_DAY, EXP_MIN, ISSUER, MINUTES, NULL_ALIAS_INT, NULL_ALIAS_STRING,
-        REFRESH_TOKEN_KEY,
-    },
+    constants::{MINUTES, NULL_ALIAS_INT, NULL_ALIAS_STRING},
+    error::ApiError,
     mailer::send_email,
+    password::{hash_password, verify_password, verify_unknown, Verified},
+    rate_limit::{
+        self, client_ip, SEND_CODE_EMAIL, SEND_CODE_IP, SIGN_IN_EMAIL, SIGN_IN_IP, VERIFICATION_ID,
+        VERIFICATION_IP,
+    },
+    role::Role,
+    session::{create_session, revoke_sessions, rotate_session, AuthSession},
+};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::USER_AGENT, HeaderMap},
+    Extension, Json,
 };
-use axum::{extract::State, Json};
 use chrono::{DateTime, Duration, Utc};
 use hyper::StatusCode;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use jsonwebtoken::{decode, DecodingKey, TokenData, Validation};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+use std::net::SocketAddr;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Authentication {
@@ -26,11 +37,28 @@ pub struct Authentication {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub iss: String,
     pub sub: String,
     pub exp: usize,
     pub iat: usize,
+    #[serde(default)]
+    pub sid: i32,
+    #[serde(default)]
+    pub jti: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser {
+    pub users_id: i32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,21 +67,53 @@ pub struct Token {
     pub refresh_token: String,
 }
 
+fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+}
+
+// deletes the verified, unexpired code sent to email, None when there is no
+// such code so it cannot be used for another email or a second time
+async fn consume_verification(
+    tx: &mut Transaction<'static, Postgres>,
+    payload: &Authentication,
+) -> Result<Option<i32>, sqlx::Error> {
+    let delete: Option<(i32,)> = sqlx::query_as("DELETE FROM public.verification WHERE (verification_id = $1 AND reference = $2 AND code = $3 AND email = $4 AND users_id IS NULL AND verified = true AND expire > $5) RETURNING verification_id")
+        .bind(payload.verification_id)
+        .bind(payload.reference)
+        .bind(payload.code)
+        .bind(&payload.email)
+        .bind(Utc::now())
+        .fetch_optional(&mut **tx)
+        .await?;
+    Ok(delete.map(|(verification_id,)| verification_id))
+}
+
 pub async fn create_verification(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, config, .. }): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<Authentication>,
-) -> Result<Json<Authentication>, StatusCode> {
+) -> Result<Json<Authentication>, ApiError> {
+    let ip = client_ip(&config, address, &headers);
+    let keys = [
+        (&SEND_CODE_EMAIL, payload.email.as_str()),
+        (&SEND_CODE_IP, &ip),
+    ];
+    rate_limit::attempt(&pool, &keys).await?;
     let reference = rand::thread_rng().gen_range(1..=99);
     let code = rand::thread_rng().gen_range(10000..=999999);
     let expire: DateTime<Utc> = Utc::now() + Duration::minutes(MINUTES);
-    let insert: Result<(i32, i32), sqlx::Error> = sqlx::query_as("INSERT INTO public.verification(reference, code, expire) VALUES ($1, $2, $3) RETURNING verification_id, reference")
+    let insert: Result<(i32, i32), sqlx::Error> = sqlx::query_as("INSERT INTO public.verification(reference, code, expire, email) VALUES ($1, $2, $3, $4) RETURNING verification_id, reference")
         .bind(reference)
         .bind(code)
         .bind(expire)
+        .bind(&payload.email)
         .fetch_one(&pool)
         .await;
     if let Ok((verification_id, reference)) = insert {
-        let sent = send_email(&payload.email, reference, code);
+        let sent = send_email(&config, &payload.email, reference, code);
         if let Ok(_) = sent {
             Ok(Json(Authentication {
                 verification_id,
@@ -66,21 +126,31 @@ pub async fn create_verification(
                 users_id: NULL_ALIAS_INT,
             }))
         } else {
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into())
         }
     } else {
-        Err(StatusCode::INTERNAL_SERVER_ERROR)
+        Err(StatusCode::INTERNAL_SERVER_ERROR.into())
     }
 }
 
 pub async fn validate_verification(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, config, .. }): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<Authentication>,
-) -> Result<Json<Authentication>, StatusCode> {
-    let fetch: Result<Option<(i32, DateTime<Utc>)>, sqlx::Error> = sqlx::query_as("SELECT verification_id, expire FROM public.verification WHERE (verification_id = $1 AND reference = $2 AND code = $3 AND verified = false)")
+) -> Result<Json<Authentication>, ApiError> {
+    let ip = client_ip(&config, address, &headers);
+    let verification_id = payload.verification_id.to_string();
+    let keys = [
+        (&VERIFICATION_ID, verification_id.as_str()),
+        (&VERIFICATION_IP, &ip),
+    ];
+    rate_limit::attempt(&pool, &keys).await?;
+    let fetch: Result<Option<(i32, DateTime<Utc>)>, sqlx::Error> = sqlx::query_as("SELECT verification_id, expire FROM public.verification WHERE (verification_id = $1 AND reference = $2 AND code = $3 AND email = $4 AND users_id IS NULL AND verified = false)")
         .bind(payload.verification_id)
         .bind(payload.reference)
         .bind(payload.code)
+        .bind(&payload.email)
         .fetch_optional(&pool)
         .await;
     match fetch {
@@ -90,169 +160,155 @@ pub async fn validate_verification(
                     let date = Utc::now();
                     if expire > date {
                         let update = sqlx::query("UPDATE public.verification SET verified = true WHERE verification_id = $1").bind(verification_id).execute(&pool).await;
-                        match update {
-                            Ok(_) => Ok(Json(Authentication {
-                                verification_id: payload.verification_id,
-                                reference: payload.reference,
-                                code: payload.code,
-                                email: payload.email,
-                                password: payload.password,
-                                access_token: payload.access_token,
-                                refresh_token: payload.refresh_token,
-                                users_id: payload.users_id,
-                            })),
-                            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+                        if update.is_err()
+                            || rate_limit::clear(&pool, &keys[..1]).await.is_err()
+                            || rate_limit::refund(&pool, &keys[1..]).await.is_err()
+                        {
+                            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
                         }
+                        Ok(Json(Authentication {
+                            verification_id: payload.verification_id,
+                            reference: payload.reference,
+                            code: payload.code,
+                            email: payload.email,
+                            password: payload.password,
+                            access_token: payload.access_token,
+                            refresh_token: payload.refresh_token,
+                            users_id: payload.users_id,
+                        }))
                     } else {
-                        Err(StatusCode::BAD_REQUEST)
+                        Err(StatusCode::BAD_REQUEST.into())
                     }
                 }
-                None => Err(StatusCode::BAD_REQUEST),
+                None => Err(StatusCode::BAD_REQUEST.into()),
             }
         }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
+// a verified code is only good for the email it was sent to and is used up
+// by the account it creates
 pub async fn create_new_account(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, config, .. }): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<Authentication>,
 ) -> Result<Json<Authentication>, StatusCode> {
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    match consume_verification(&mut tx, &payload).await {
+        Ok(ok) => match ok {
+            Some(_) => (),
+            None => return Err(StatusCode::BAD_REQUEST),
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
     let email = payload.email;
     let password = payload.password;
-    let fetch: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("SELECT verification_id FROM public.verification WHERE (verification_id = $1 AND reference = $2 AND code = $3 AND verified = true)")
-        .bind(payload.verification_id)
-        .bind(payload.reference)
-        .bind(payload.code)
-        .fetch_optional(&pool)
+    let hashed = match hash_password(&password).await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let date = Utc::now();
+    let insert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.users (name, email, password, created_date, latest_sign_in) VALUES ($1, $2, $3, $4, $5) RETURNING users_id")
+        .bind(*email.split("@").collect::<Vec<&str>>().get(0).unwrap())
+        .bind(&email)
+        .bind(&hashed)
+        .bind(date)
+        .bind(date)
+        .fetch_one(&mut *tx)
         .await;
-    match fetch {
-        Ok(ok) => match ok {
-            Some(_) => {
-                let date = Utc::now();
-                let insert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.users (name, email, password, created_date, latest_sign_in) VALUES ($1, $2, $3, $4, $5) RETURNING users_id")
-                    .bind(*email.split("@").collect::<Vec<&str>>().get(0).unwrap())
-                    .bind(&email)
-                    .bind(blake3::hash(password.as_bytes()).to_string())
-                    .bind(date)
-                    .bind(date)
-                    .fetch_one(&pool)
-                    .await;
-                match insert {
-                    Ok((users_id,)) => {
-                        let access_claims = Claims {
-                            iat: date.timestamp() as usize,
-                            exp: (date + Duration::minutes(EXP_MIN)).timestamp() as usize,
-                            iss: ISSUER.to_string(),
-                            sub: users_id.to_string(),
-                        };
-                        let refresh_claims = Claims {
-                            iat: date.timestamp() as usize,
-                            exp: (date + Duration::days(EXP_DAY)).timestamp() as usize,
-                            iss: ISSUER.to_string(),
-                            sub: users_id.to_string(),
-                        };
-                        let access_token = encode(
-                            &Header::default(),
-                            &access_claims,
-                            &EncodingKey::from_secret(ACCESS_TOKEN_KEY.as_ref()),
-                        );
-                        let refresh_token = encode(
-                            &Header::default(),
-                            &refresh_claims,
-                            &EncodingKey::from_secret(REFRESH_TOKEN_KEY.as_ref()),
-                        );
-                        Ok(Json(Authentication {
-                            verification_id: NULL_ALIAS_INT,
-                            reference: NULL_ALIAS_INT,
-                            code: NULL_ALIAS_INT,
-                            email,
-                            password,
-                            access_token: access_token.unwrap(),
-                            refresh_token: refresh_token.unwrap(),
-                            users_id,
-                        }))
-                    }
-                    Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-                }
-            }
-            None => Err(StatusCode::BAD_REQUEST),
-        },
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    let users_id = match insert {
+        Ok((users_id,)) => users_id,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    if tx.commit().await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    let token = create_session(&pool, &config, users_id, user_agent(&headers)).await?;
+    Ok(Json(Authentication {
+        verification_id: NULL_ALIAS_INT,
+        reference: NULL_ALIAS_INT,
+        code: NULL_ALIAS_INT,
+        email,
+        password,
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+        users_id,
+    }))
 }
 
 pub async fn sign_in(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, config, .. }): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<Authentication>,
-) -> Result<Json<Authentication>, StatusCode> {
+) -> Result<Json<Authentication>, ApiError> {
     let email = payload.email;
     let password = payload.password;
-    let fetch: Result<Option<(i32,)>, sqlx::Error> =
-        sqlx::query_as("SELECT users_id FROM public.users WHERE (email = $1 AND password = $2)")
+    let ip = client_ip(&config, address, &headers);
+    let keys = [(&SIGN_IN_EMAIL, email.as_str()), (&SIGN_IN_IP, &ip)];
+    rate_limit::attempt(&pool, &keys).await?;
+    let fetch: Result<Option<(i32, String)>, sqlx::Error> =
+        sqlx::query_as("SELECT users_id, password FROM public.users WHERE email = $1")
             .bind(&email)
-            .bind(blake3::hash(password.as_bytes()).to_string())
             .fetch_optional(&pool)
             .await;
     if let Ok(ok) = fetch {
-        if let Some((users_id,)) = ok {
+        if let Some((users_id, stored)) = ok {
+            let rehashed = match verify_password(&password, &stored).await {
+                Verified::Valid => None,
+                Verified::ValidLegacy => match hash_password(&password).await {
+                    Ok(ok) => Some(ok),
+                    Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+                },
+                Verified::Invalid => return Err(StatusCode::BAD_REQUEST.into()),
+            };
             let date = Utc::now();
-            let update =
-                sqlx::query("UPDATE public.users SET latest_sign_in = $1 WHERE users_id = $2")
-                    .bind(date)
-                    .bind(users_id)
-                    .execute(&pool)
-                    .await;
-            match update {
-                Ok(_) => {
-                    let access_claims = Claims {
-                        iat: date.timestamp() as usize,
-                        exp: (date + Duration::minutes(EXP_MIN)).timestamp() as usize,
-                        iss: ISSUER.to_string(),
-                        sub: users_id.to_string(),
-                    };
-                    let refresh_claims = Claims {
-                        iat: date.timestamp() as usize,
-                        exp: (date + Duration::days(EXP_DAY)).timestamp() as usize,
-                        iss: ISSUER.to_string(),
-                        sub: users_id.to_string(),
-                    };
-                    let access_token = encode(
-                        &Header::default(),
-                        &access_claims,
-                        &EncodingKey::from_secret(ACCESS_TOKEN_KEY.as_ref()),
-                    );
-                    let refresh_token = encode(
-                        &Header::default(),
-                        &refresh_claims,
-                        &EncodingKey::from_secret(REFRESH_TOKEN_KEY.as_ref()),
-                    );
-                    Ok(Json(Authentication {
-                        verification_id: NULL_ALIAS_INT,
-                        reference: NULL_ALIAS_INT,
-                        code: NULL_ALIAS_INT,
-                        email,
-                        password,
-                        access_token: access_token.unwrap(),
-                        refresh_token: refresh_token.unwrap(),
-                        users_id,
-                    }))
-                }
-                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            let update = sqlx::query("UPDATE public.users SET latest_sign_in = $1, password = COALESCE($3, password) WHERE users_id = $2")
+                .bind(date)
+                .bind(users_id)
+                .bind(rehashed)
+                .execute(&pool)
+                .await;
+            if update.is_err()
+                || rate_limit::clear(&pool, &keys[..1]).await.is_err()
+                || rate_limit::refund(&pool, &keys[1..]).await.is_err()
+            {
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
             }
+            let token = create_session(&pool, &config, users_id, user_agent(&headers)).await?;
+            Ok(Json(Authentication {
+                verification_id: NULL_ALIAS_INT,
+                reference: NULL_ALIAS_INT,
+                code: NULL_ALIAS_INT,
+                email,
+                password,
+                access_token: token.access_token,
+                refresh_token: token.refresh_token,
+                users_id,
+            }))
         } else {
-            Err(StatusCode::BAD_REQUEST)
+            verify_unknown(&password).await;
+            Err(StatusCode::BAD_REQUEST.into())
         }
     } else {
-        Err(StatusCode::INTERNAL_SERVER_ERROR)
+        Err(StatusCode::INTERNAL_SERVER_ERROR.into())
     }
 }
 
 pub async fn create_verification_forgot(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, config, .. }): State<AppState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<Authentication>,
-) -> Result<Json<Authentication>, StatusCode> {
+) -> Result<Json<Authentication>, ApiError> {
     let email = payload.email;
+    let ip = client_ip(&config, address, &headers);
+    let keys = [(&SEND_CODE_EMAIL, email.as_str()), (&SEND_CODE_IP, &ip)];
+    rate_limit::attempt(&pool, &keys).await?;
     let fetch = sqlx::query("SELECT users_id FROM public.users WHERE email = $1")
         .bind(&email)
         .fetch_all(&pool)
@@ -262,14 +318,15 @@ pub async fn create_verification_forgot(
             let reference = rand::thread_rng().gen_range(1..=99);
             let code = rand::thread_rng().gen_range(10000..=999999);
             let expire: DateTime<Utc> = Utc::now() + Duration::minutes(MINUTES);
-            let insert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.verification(reference, code, expire) VALUES ($1, $2, $3) RETURNING verification_id")
+            let insert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.verification(reference, code, expire, email) VALUES ($1, $2, $3, $4) RETURNING verification_id")
                 .bind(reference)
                 .bind(code)
                 .bind(expire)
+                .bind(&email)
                 .fetch_one(&pool)
                 .await;
             if let Ok((verification_id,)) = insert {
-                let sent = send_email(&email, reference, code);
+                let sent = send_email(&config, &email, reference, code);
                 if let Ok(_) = sent {
                     Ok(Json(Authentication {
                         verification_id,
@@ -282,159 +339,167 @@ pub async fn create_verification_forgot(
                         users_id: NULL_ALIAS_INT,
                     }))
                 } else {
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
                 }
             } else {
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into())
             }
         } else {
-            Err(StatusCode::BAD_REQUEST)
+            Err(StatusCode::BAD_REQUEST.into())
         }
     } else {
-        Err(StatusCode::INTERNAL_SERVER_ERROR)
+        Err(StatusCode::INTERNAL_SERVER_ERROR.into())
     }
 }
 
+// the verified code must have been sent to the email being reset and is used
+// up in the same transaction as the new password
 pub async fn reset_password(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, config, .. }): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<Authentication>,
 ) -> Result<Json<Authentication>, StatusCode> {
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    match consume_verification(&mut tx, &payload).await {
+        Ok(ok) => match ok {
+            Some(_) => (),
+            None => return Err(StatusCode::BAD_REQUEST),
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
     let email = payload.email;
     let password = payload.password;
-    let fetch: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("SELECT verification_id FROM public.verification WHERE (verification_id = $1 AND reference = $2 AND code = $3 AND verified = true)")
-        .bind(payload.verification_id)
-        .bind(payload.reference)
-        .bind(payload.code)
-        .fetch_optional(&pool)
+    let hashed = match hash_password(&password).await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let date = Utc::now();
+    let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("UPDATE public.users SET password = $1, latest_sign_in = $2 WHERE email = $3 RETURNING users_id")
+        .bind(&hashed)
+        .bind(date)
+        .bind(&email)
+        .fetch_optional(&mut *tx)
         .await;
-    match fetch {
+    let users_id = match update {
         Ok(ok) => match ok {
-            Some(_) => {
-                let date = Utc::now();
-                let update: Result<(i32,), sqlx::Error> = sqlx::query_as("UPDATE public.users SET password = $1, latest_sign_in = $2 WHERE email = $3 RETURNING users_id")
-                    .bind(blake3::hash(password.as_bytes()).to_string())
-                    .bind(date)
-                    .bind(&email)
-                    .fetch_one(&pool)
-                    .await;
-                if let Ok((users_id,)) = update {
-                    let access_claims = Claims {
-                        iat: date.timestamp() as usize,
-                        exp: (date + Duration::minutes(EXP_MIN)).timestamp() as usize,
-                        iss: ISSUER.to_string(),
-                        sub: users_id.to_string(),
-                    };
-                    let refresh_claims = Claims {
-                        iat: date.timestamp() as usize,
-                        exp: (date + Duration::days(EXP_DAY)).timestamp() as usize,
-                        iss: ISSUER.to_string(),
-                        sub: users_id.to_string(),
-                    };
-                    let access_token = encode(
-                        &Header::default(),
-                        &access_claims,
-                        &EncodingKey::from_secret(ACCESS_TOKEN_KEY.as_ref()),
-                    );
-                    let refresh_token = encode(
-                        &Header::default(),
-                        &refresh_claims,
-                        &EncodingKey::from_secret(REFRESH_TOKEN_KEY.as_ref()),
-                    );
-                    Ok(Json(Authentication {
-                        verification_id: NULL_ALIAS_INT,
-                        reference: NULL_ALIAS_INT,
-                        code: NULL_ALIAS_INT,
-                        email,
-                        password,
-                        access_token: access_token.unwrap(),
-                        refresh_token: refresh_token.unwrap(),
-                        users_id,
-                    }))
-                } else {
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            }
-            None => Err(StatusCode::BAD_REQUEST),
+            Some((users_id,)) => users_id,
+            None => return Err(StatusCode::BAD_REQUEST),
         },
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    if tx.commit().await.is_err() || revoke_sessions(&pool, users_id, None).await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    let token = create_session(&pool, &config, users_id, user_agent(&headers)).await?;
+    Ok(Json(Authentication {
+        verification_id: NULL_ALIAS_INT,
+        reference: NULL_ALIAS_INT,
+        code: NULL_ALIAS_INT,
+        email,
+        password,
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+        users_id,
+    }))
 }
 
 pub async fn renew_token(
+    State(AppState { pool, config, .. }): State<AppState>,
     Json(payload): Json<Authentication>,
-) -> Result<Json<Authentication>, StatusCode> {
+) -> Result<Json<Authentication>, ApiError> {
     let token = decode::<Claims>(
         &payload.refresh_token,
-        &DecodingKey::from_secret(REFRESH_TOKEN_KEY.as_ref()),
+        &DecodingKey::from_secret(config.refresh_token_key.as_ref()),
         &Validation::default(),
     );
     if let Ok(TokenData { header: _, claims }) = token {
-        let date = Utc::now();
-        let access_claims = Claims {
-            iat: date.timestamp() as usize,
-            exp: (date + Duration::minutes(EXP_MIN)).timestamp() as usize,
-            iss: ISSUER.to_string(),
-            sub: claims.sub.to_string(),
-        };
-        let refresh_claims = Claims {
-            iat: date.timestamp() as usize,
-            exp: (date + Duration::days(EXP_DAY)).timestamp() as usize,
-            iss: ISSUER.to_string(),
-            sub: claims.sub.to_string(),
-        };
-        let access_token = encode(
-            &Header::default(),
-            &access_claims,
-            &EncodingKey::from_secret(ACCESS_TOKEN_KEY.as_ref()),
-        );
-        let refresh_token = encode(
-            &Header::default(),
-            &refresh_claims,
-            &EncodingKey::from_secret(REFRESH_TOKEN_KEY.as_ref()),
-        );
+        let token = rotate_session(&pool, &config, &claims).await?;
         Ok(Json(Authentication {
             verification_id: NULL_ALIAS_INT,
             reference: NULL_ALIAS_INT,
             code: NULL_ALIAS_INT,
             email: NULL_ALIAS_STRING.to_string(),
             password: NULL_ALIAS_STRING.to_string(),
-            access_token: access_token.unwrap(),
-            refresh_token: refresh_token.unwrap(),
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
             users_id: NULL_ALIAS_INT,
         }))
     } else {
-        Err(StatusCode::UNAUTHORIZED)
+        Err(StatusCode::UNAUTHORIZED.into())
     }
 }
 
+// signs out every other session, the one changing the password stays valid
+// the current password is required so a stolen session cannot lock the owner
+// out of their other sessions
 pub async fn change_password(
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<Authentication>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Extension(AuthSession { sessions_id }): Extension<AuthSession>,
+    Json(payload): Json<ChangePassword>,
 ) -> StatusCode {
+    let fetch: Result<Option<(String,)>, sqlx::Error> =
+        sqlx::query_as("SELECT password FROM public.users WHERE users_id = $1")
+            .bind(users_id)
+            .fetch_optional(&pool)
+            .await;
+    match fetch {
+        Ok(ok) => match ok {
+            Some((stored,)) => match verify_password(&payload.current_password, &stored).await {
+                Verified::Valid | Verified::ValidLegacy => (),
+                Verified::Invalid => return StatusCode::BAD_REQUEST,
+            },
+            None => return StatusCode::BAD_REQUEST,
+        },
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    }
+    let hashed = match hash_password(&payload.password).await {
+        Ok(ok) => ok,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
     let update = sqlx::query("UPDATE public.users SET password = $1 WHERE users_id = $2")
-        .bind(blake3::hash(payload.password.as_bytes()).to_string())
-        .bind(payload.users_id)
+        .bind(&hashed)
+        .bind(users_id)
         .execute(&pool)
         .await;
-    match update {
+    if update.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    match revoke_sessions(&pool, users_id, Some(sessions_id)).await {
         Ok(_) => StatusCode::OK,
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
 pub async fn delete_account(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<Authentication>,
 ) -> StatusCode {
-    let delete: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("DELETE FROM public.users WHERE (users_id = $1 AND email = $2 AND password = $3) RETURNING users_id")
-        .bind(payload.users_id)
-        .bind(payload.email)
-        .bind(blake3::hash(payload.password.as_bytes()).to_string())
-        .fetch_optional(&pool)
-        .await;
-    match delete {
+    let fetch: Result<Option<(String,)>, sqlx::Error> =
+        sqlx::query_as("SELECT password FROM public.users WHERE (users_id = $1 AND email = $2)")
+            .bind(users_id)
+            .bind(&payload.email)
+            .fetch_optional(&pool)
+            .await;
+    match fetch {
         Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
+            Some((stored,)) => match verify_password(&payload.password, &stored).await {
+                Verified::Valid | Verified::ValidLegacy => {
+                    let delete = sqlx::query("DELETE FROM public.users WHERE users_id = $1")
+                        .bind(users_id)
+                        .execute(&pool)
+                        .await;
+                    match delete {
+                        Ok(_) => StatusCode::OK,
+                        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                    }
+                }
+                Verified::Invalid => StatusCode::BAD_REQUEST,
+            },
             None => StatusCode::BAD_REQUEST,
         },
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -0,0 +1,178 @@
+use axum::http::HeaderName;
+use email_address::EmailAddress;
+use serde::Deserialize;
+use std::{
+    env, fmt, fs,
+    net::{IpAddr, SocketAddr},
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database_url: String,
+    pub bind_address: SocketAddr,
+    pub access_token_key: String,
+    pub refresh_token_key: String,
+    pub issuer: String,
+    pub public_url: String,
+    pub smtp_relay: String,
+    pub smtp_email: String,
+    pub smtp_password: String,
+    pub aws_access_key_id: Option<String>,
+    pub aws_secret_access_key: Option<String>,
+    pub aws_region: String,
+    pub bucket_name: String,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub client_ip_header: HeaderName,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    database_url: Option<String>,
+    bind_address: Option<String>,
+    access_token_key: Option<String>,
+    refresh_token_key: Option<String>,
+    issuer: Option<String>,
+    public_url: Option<String>,
+    smtp_relay: Option<String>,
+    smtp_email: Option<String>,
+    smtp_password: Option<String>,
+    aws_access_key_id: Option<String>,
+    aws_secret_access_key: Option<String>,
+    aws_region: Option<String>,
+    bucket_name: Option<String>,
+    trusted_proxies: Option<String>,
+    client_ip_header: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Missing(&'static str),
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "cannot read config file {path}: {err}"),
+            ConfigError::Parse(path, err) => write!(f, "cannot parse config file {path}: {err}"),
+            ConfigError::Missing(key) => write!(
+                f,
+                "missing config value {key}, set it in the config file or as {}",
+                key.to_uppercase()
+            ),
+            ConfigError::Invalid(key, reason) => write!(f, "invalid config value {key}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn value(key: &'static str, file: Option<String>) -> Option<String> {
+    match env::var(key.to_uppercase()) {
+        Ok(ok) if !ok.trim().is_empty() => Some(ok),
+        _ => file.filter(|some| !some.trim().is_empty()),
+    }
+}
+
+fn required(key: &'static str, file: Option<String>) -> Result<String, ConfigError> {
+    value(key, file).ok_or(ConfigError::Missing(key))
+}
+
+impl Config {
+    // values are read from the toml file in APP_CONFIG (default config.toml, optional),
+    // environment variables with the upper case key take precedence
+    pub fn load() -> Result<Config, ConfigError> {
+        let path = env::var("APP_CONFIG").ok();
+        let file = match &path {
+            Some(path) => {
+                Some(fs::read_to_string(path).map_err(|err| ConfigError::Read(path.clone(), err))?)
+            }
+            None => fs::read_to_string("config.toml").ok(),
+        };
+        let file: ConfigFile = match file {
+            Some(text) => toml::from_str(&text).map_err(|err| {
+                ConfigError::Parse(path.unwrap_or("config.toml".to_string()), err)
+            })?,
+            None => ConfigFile::default(),
+        };
+        Config::from_file(file)
+    }
+
+    fn from_file(file: ConfigFile) -> Result<Config, ConfigError> {
+        let database_url = required("database_url", file.database_url)?;
+        if !database_url.starts_with("postgres://") && !database_url.starts_with("postgresql://") {
+            return Err(ConfigError::Invalid(
+                "database_url",
+                "expected a postgres:// url".to_string(),
+            ));
+        }
+        let bind_address = value("bind_address", file.bind_address)
+            .unwrap_or("0.0.0.0:3000".to_string())
+            .parse::<SocketAddr>()
+            .map_err(|err| ConfigError::Invalid("bind_address", err.to_string()))?;
+        let access_token_key = required("access_token_key", file.access_token_key)?;
+        let refresh_token_key = required("refresh_token_key", file.refresh_token_key)?;
+        if access_token_key == refresh_token_key {
+            return Err(ConfigError::Invalid(
+                "refresh_token_key",
+                "must differ from access_token_key".to_string(),
+            ));
+        }
+        let public_url = required("public_url", file.public_url)?
+            .trim_end_matches('/')
+            .to_string();
+        if !public_url.starts_with("http://") && !public_url.starts_with("https://") {
+            return Err(ConfigError::Invalid(
+                "public_url",
+                "expected an http:// or https:// url".to_string(),
+            ));
+        }
+        let smtp_email = required("smtp_email", file.smtp_email)?;
+        if !EmailAddress::is_valid(&smtp_email) {
+            return Err(ConfigError::Invalid(
+                "smtp_email",
+                format!("{smtp_email} is not an email address"),
+            ));
+        }
+        let aws_access_key_id = value("aws_access_key_id", file.aws_access_key_id);
+        let aws_secret_access_key = value("aws_secret_access_key", file.aws_secret_access_key);
+        if aws_access_key_id.is_some() != aws_secret_access_key.is_some() {
+            return Err(ConfigError::Invalid(
+                "aws_secret_access_key",
+                "aws_access_key_id and aws_secret_access_key must be set together".to_string(),
+            ));
+        }
+        let trusted_proxies = value("trusted_proxies", file.trusted_proxies)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| proxy.parse::<IpAddr>())
+            .collect::<Result<Vec<IpAddr>, _>>()
+            .map_err(|err| ConfigError::Invalid("trusted_proxies", err.to_string()))?;
+        let client_ip_header = value("client_ip_header", file.client_ip_header)
+            .unwrap_or("x-forwarded-for".to_string())
+            .parse::<HeaderName>()
+            .map_err(|err| ConfigError::Invalid("client_ip_header", err.to_string()))?;
+        Ok(Config {
+            database_url,
+            bind_address,
+            access_token_key,
+            refresh_token_key,
+            issuer: required("issuer", file.issuer)?,
+            public_url,
+            smtp_relay: value("smtp_relay", file.smtp_relay)
+                .unwrap_or("smtp.gmail.com".to_string()),
+            smtp_email,
+            smtp_password: required("smtp_password", file.smtp_password)?,
+            aws_access_key_id,
+            aws_secret_access_key,
+            aws_region: required("aws_region", file.aws_region)?,
+            bucket_name: required("bucket_name", file.bucket_name)?,
+            trusted_proxies,
+            client_ip_header,
+        })
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    app_state::AppState,
+    authentication::{AuthUser, Authentication},
+    constants::{MINUTES, NULL_ALIAS_INT, NULL_ALIAS_STRING},
+    error::ApiError,
+    mailer::{send_email, send_email_change_notice},
+    rate_limit::{
+        self, client_ip, SEND_CODE_EMAIL, SEND_CODE_IP, VERIFICATION_ID, VERIFICATION_IP,
+    },
+    session::revoke_sessions,
+};
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::HeaderMap,
+    response::Html,
+    Extension, Form, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+const CANCEL_DAY: i64 = 7;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelToken {
+    pub token: String,
+}
+
+fn hash_token(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
+
+fn email_taken(email: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::CONFLICT,
+        "email_taken",
+        format!("{email} is used by another account"),
+    )
+}
+
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .is_some_and(|err| err.is_unique_violation())
+}
+
+// the code goes to the new email, validate_email and validate_email_unique run
+// on the new email before this handler
+pub async fn create_verification_email(
+    State(AppState { pool, config, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<Authentication>,
+) -> Result<Json<Authentication>, ApiError> {
+    let ip = client_ip(&config, address, &headers);
+    let keys = [
+        (&SEND_CODE_EMAIL, payload.email.as_str()),
+        (&SEND_CODE_IP, &ip),
+    ];
+    rate_limit::attempt(&pool, &keys).await?;
+    let reference = rand::thread_rng().gen_range(1..=99);
+    let code = rand::thread_rng().gen_range(10000..=999999);
+    let expire: DateTime<Utc> = Utc::now() + Duration::minutes(MINUTES);
+    let insert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.verification(reference, code, expire, users_id, email) VALUES ($1, $2, $3, $4, $5) RETURNING verification_id")
+        .bind(reference)
+        .bind(code)
+        .bind(expire)
+        .bind(users_id)
+        .bind(&payload.email)
+        .fetch_one(&pool)
+        .await;
+    let verification_id = match insert {
+        Ok((ok,)) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    if send_email(&config, &payload.email, reference, code).is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(Json(Authentication {
+        verification_id,
+        reference,
+        code: NULL_ALIAS_INT,
+        email: payload.email,
+        password: NULL_ALIAS_STRING.to_string(),
+        access_token: NULL_ALIAS_STRING.to_string(),
+        refresh_token: NULL_ALIAS_STRING.to_string(),
+        users_id,
+    }))
+}
+
+// checks the code sent to the new email and swaps the email, the previous
+// email is mailed a cancel link once the change is committed and the change is
+// reverted when that notice cannot be sent, so the email never changes without
+// the previous owner being told and no lock is held while mailing
+pub async fn change_email(
+    State(AppState { pool, config, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<Authentication>,
+) -> Result<Json<Authentication>, ApiError> {
+    let ip = client_ip(&config, address, &headers);
+    let verification_id = payload.verification_id.to_string();
+    let keys = [
+        (&VERIFICATION_ID, verification_id.as_str()),
+        (&VERIFICATION_IP, &ip),
+    ];
+    rate_limit::attempt(&pool, &keys).await?;
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let fetch: Result<Option<(String, DateTime<Utc>)>, sqlx::Error> = sqlx::query_as("SELECT email, expire FROM public.verification WHERE (verification_id = $1 AND reference = $2 AND code = $3 AND users_id = $4 AND email IS NOT NULL AND verified = false) FOR UPDATE")
+        .bind(payload.verification_id)
+        .bind(payload.reference)
+        .bind(payload.code)
+        .bind(users_id)
+        .fetch_optional(&mut *tx)
+        .await;
+    let email = match fetch {
+        Ok(ok) => match ok {
+            Some((email, expire)) if expire > Utc::now() => email,
+            Some(_) => {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "verification_expired",
+                    "verification code has expired",
+                ))
+            }
+            None => return Err(StatusCode::BAD_REQUEST.into()),
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    // the new email may have been taken since the code was sent
+    let update: Result<Option<(String,)>, sqlx::Error> = sqlx::query_as("UPDATE public.users SET email = $1 FROM public.users AS previous WHERE (users.users_id = $2 AND previous.users_id = users.users_id AND NOT EXISTS (SELECT 1 FROM public.users AS taken WHERE taken.email = $1)) RETURNING previous.email")
+        .bind(&email)
+        .bind(users_id)
+        .fetch_optional(&mut *tx)
+        .await;
+    let previous_email = match update {
+        Ok(Some((ok,))) => ok,
+        Ok(None) => return Err(email_taken(&email)),
+        Err(err) if is_unique_violation(&err) => return Err(email_taken(&email)),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let token = Uuid::new_v4().simple().to_string();
+    let update = sqlx::query("UPDATE public.verification SET verified = true, previous_email = $1, cancel_token = $2, cancel_expire = $3 WHERE verification_id = $4")
+        .bind(&previous_email)
+        .bind(hash_token(&token))
+        .bind(Utc::now() + Duration::days(CANCEL_DAY))
+        .bind(payload.verification_id)
+        .execute(&mut *tx)
+        .await;
+    if update.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    if tx.commit().await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    let cancel_url = format!("{}/cancel_email_change?token={token}", config.public_url);
+    let notice = tokio::task::spawn_blocking({
+        let email = email.clone();
+        move || send_email_change_notice(&config, &previous_email, &email, &cancel_url)
+    })
+    .await;
+    if !matches!(notice, Ok(Ok(_))) {
+        // the verification stays used, the user asks for a new code
+        let _ = revert_email_change(&pool, &token).await;
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    if rate_limit::clear(&pool, &keys[..1]).await.is_err()
+        || rate_limit::refund(&pool, &keys[1..]).await.is_err()
+    {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(Json(Authentication {
+        verification_id: NULL_ALIAS_INT,
+        reference: NULL_ALIAS_INT,
+        code: NULL_ALIAS_INT,
+        email,
+        password: NULL_ALIAS_STRING.to_string(),
+        access_token: NULL_ALIAS_STRING.to_string(),
+        refresh_token: NULL_ALIAS_STRING.to_string(),
+        users_id,
+    }))
+}
+
+fn invalid_cancel_token() -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        "invalid_cancel_token",
+        "the link is invalid, used or has expired",
+    )
+}
+
+// moves the account back to the previous email and uses up the token, a
+// later change of the same account is left alone, the users_id when the
+// token was valid
+async fn revert_email_change(pool: &Pool<Postgres>, token: &str) -> Result<i32, ApiError> {
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let fetch: Result<Option<(i32, i32, String, String)>, sqlx::Error> = sqlx::query_as("SELECT verification_id, users_id, email, previous_email FROM public.verification WHERE (cancel_token = $1 AND cancel_expire > $2) FOR UPDATE")
+        .bind(hash_token(token))
+        .bind(Utc::now())
+        .fetch_optional(&mut *tx)
+        .await;
+    let (verification_id, users_id, email, previous_email) = match fetch {
+        Ok(ok) => match ok {
+            Some(ok) => ok,
+            None => return Err(invalid_cancel_token()),
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let update =
+        sqlx::query("UPDATE public.users SET email = $1 WHERE (users_id = $2 AND email = $3)")
+            .bind(&previous_email)
+            .bind(users_id)
+            .bind(&email)
+            .execute(&mut *tx)
+            .await;
+    match update {
+        Ok(_) => (),
+        Err(err) if is_unique_violation(&err) => return Err(email_taken(&previous_email)),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+    let update = sqlx::query("UPDATE public.verification SET cancel_token = NULL, cancel_expire = NULL WHERE verification_id = $1")
+        .bind(verification_id)
+        .execute(&mut *tx)
+        .await;
+    if update.is_err() || tx.commit().await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(users_id)
+}
+
+// opened from the link in the notice, only asks for confirmation since mail
+// scanners and link previews follow links, the token is posted back by the form
+pub async fn confirm_cancel_email_change(
+    State(AppState { pool, .. }): State<AppState>,
+    Query(payload): Query<CancelToken>,
+) -> Result<Html<String>, ApiError> {
+    let fetch: Result<Option<(String,)>, sqlx::Error> = sqlx::query_as(
+        "SELECT email FROM public.verification WHERE (cancel_token = $1 AND cancel_expire > $2)",
+    )
+    .bind(hash_token(&payload.token))
+    .bind(Utc::now())
+    .fetch_optional(&pool)
+    .await;
+    match fetch {
+        Ok(ok) => match ok {
+            // the token matched a stored hash so it is one of ours and safe
+            // to echo into the page
+            Some((email,)) => Ok(Html(format!(
+                "<p style=\"text-align: center\">The email of your TB789 account was changed to {}</p>
+<form method=\"post\" action=\"cancel_email_change\" style=\"text-align: center\">
+<input type=\"hidden\" name=\"token\" value=\"{}\">
+<button type=\"submit\">Cancel the email change and sign out every device</button>
+</form>",
+                email.replace('<', "&lt;").replace('>', "&gt;"),
+                payload.token
+            ))),
+            None => Err(invalid_cancel_token()),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+// posted from the confirmation page, reverts the change and signs out every
+// session since the change may not have been theirs
+pub async fn cancel_email_change(
+    State(AppState { pool, .. }): State<AppState>,
+    Form(payload): Form<CancelToken>,
+) -> Result<Html<&'static str>, ApiError> {
+    let users_id = revert_email_change(&pool, &payload.token).await?;
+    if revoke_sessions(&pool, users_id, None).await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(Html(
+        "<p style=\"text-align: center\">The email change was cancelled and every device was signed out</p>",
+    ))
+}
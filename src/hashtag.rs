@@ -1,77 +1,267 @@
-use crate::app_state::AppState;
-use axum::{extract::State, Json};
-use chrono::Utc;
+use crate::{
+    app_state::AppState,
+    authentication::AuthUser,
+    error::ApiError,
+    plates::validate_plates_owner,
+    query::{fetch_plates_group, PlatesGroup, PlatesSearch},
+    query_builder::{escape_like, MAX_PAGE},
+};
+use axum::{extract::State, Extension, Json};
+use chrono::{Duration, Utc};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
 
-#[derive(Debug, Serialize, Deserialize)]
+const TAG_MAX_CHARS: usize = 50;
+const TRENDING_MAX_DAY: i64 = 90;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Hashtag {
     pub hashtag_id: i32,
     pub tag: String,
     pub add_date: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct HashtagCount {
+    pub hashtag_id: i32,
+    pub tag: String,
+    pub plates_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewHashtag {
+    pub tag: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlatesHashtag {
-    pub plates_hashtag_id: i32,
+    pub plates_id: i32,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlatesHashtagId {
     pub plates_id: i32,
     pub hashtag_id: i32,
-    pub add_date: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HashtagPlatesFilter {
+    pub tag: String,
+    pub sort_by: String,
+    pub limit: i32,
+    pub offset: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HashtagFilter {
+    pub search_text: String,
+    pub limit: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrendingFilter {
+    pub days: i64,
+    pub limit: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlatesId {
+    pub plates_id: i32,
+}
+
+// trims a leading #, lower cases latin letters, joins words with _, drops
+// zero width characters and folds the decomposed sara am (U+0E4D U+0E32)
+// into U+0E33 so the same thai tag typed on different keyboards matches
+pub fn normalize_tag(tag: &str) -> Result<String, ApiError> {
+    let tag = tag
+        .trim()
+        .trim_start_matches('#')
+        .replace(['\u{200B}', '\u{200C}', '\u{200D}', '\u{FEFF}'], "")
+        .replace("\u{0E4D}\u{0E32}", "\u{0E33}")
+        .to_lowercase();
+    let tag = tag.split_whitespace().collect::<Vec<&str>>().join("_");
+    let valid = tag
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || ('\u{0E00}'..='\u{0E7F}').contains(&c));
+    if tag.is_empty() || tag.chars().count() > TAG_MAX_CHARS || !valid {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_hashtag",
+            format!("hashtag must be 1 to {TAG_MAX_CHARS} letters, digits or _"),
+        ));
+    }
+    Ok(tag)
+}
+
+async fn upsert_hashtag(pool: &Pool<Postgres>, tag: &str) -> Result<Hashtag, sqlx::Error> {
+    sqlx::query_as("INSERT INTO public.hashtag(tag, add_date) VALUES ($1, $2) ON CONFLICT (tag) DO UPDATE SET tag = EXCLUDED.tag RETURNING hashtag_id, tag, add_date::TEXT")
+        .bind(tag)
+        .bind(Utc::now())
+        .fetch_one(pool)
+        .await
 }
 
 pub async fn add_new_hashtag(
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<Hashtag>,
-) -> StatusCode {
-    let add_date = Utc::now();
-    let insert = sqlx::query("INSERT INTO public.hashtag(tag, add_date) VALUES ($1, $2)")
-        .bind(payload.tag)
-        .bind(add_date)
-        .fetch_optional(&pool)
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<NewHashtag>,
+) -> Result<Json<Hashtag>, ApiError> {
+    let tag = normalize_tag(&payload.tag)?;
+    match upsert_hashtag(&pool, &tag).await {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+pub async fn add_hashtag_to_plates(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<PlatesHashtag>,
+) -> Result<Json<Hashtag>, ApiError> {
+    let tag = normalize_tag(&payload.tag)?;
+    validate_plates_owner(&pool, payload.plates_id, users_id).await?;
+    let hashtag = match upsert_hashtag(&pool, &tag).await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let insert = sqlx::query("INSERT INTO public.plates_hashtag(plates_id, hashtag_id, add_date) VALUES ($1, $2, $3) ON CONFLICT (plates_id, hashtag_id) DO NOTHING")
+        .bind(payload.plates_id)
+        .bind(hashtag.hashtag_id)
+        .bind(Utc::now())
+        .execute(&pool)
         .await;
     match insert {
+        Ok(_) => Ok(Json(hashtag)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+pub async fn remove_hashtag_from_plates(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<PlatesHashtagId>,
+) -> Result<StatusCode, ApiError> {
+    validate_plates_owner(&pool, payload.plates_id, users_id).await?;
+    let delete: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("DELETE FROM public.plates_hashtag WHERE (plates_id = $1 AND hashtag_id = $2) RETURNING plates_hashtag_id")
+        .bind(payload.plates_id)
+        .bind(payload.hashtag_id)
+        .fetch_optional(&pool)
+        .await;
+    match delete {
         Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
-            None => StatusCode::BAD_REQUEST,
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(StatusCode::BAD_REQUEST.into()),
         },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
-pub async fn add_hashtag_to_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<PlatesHashtag>,
-) -> StatusCode {
-    let unique_text = format!(
-        "plates_id({})-hashtag_id({})",
-        payload.plates_id, payload.hashtag_id
-    );
-    let fetch =
-        sqlx::query("SELECT plates_hashtag_id FROM public.plates_hashtag WHERE (unique_text = $1)")
-            .bind(&unique_text)
-            .fetch_optional(&pool)
-            .await;
+pub async fn query_plates_hashtag(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<PlatesId>,
+) -> Result<Json<Vec<Hashtag>>, StatusCode> {
+    let fetch: Result<Vec<Hashtag>, sqlx::Error> = sqlx::query_as("SELECT hashtag.hashtag_id, hashtag.tag, plates_hashtag.add_date::TEXT FROM public.plates_hashtag INNER JOIN public.hashtag ON hashtag.hashtag_id = plates_hashtag.hashtag_id WHERE plates_hashtag.plates_id = $1 ORDER BY plates_hashtag.add_date ASC")
+        .bind(payload.plates_id)
+        .fetch_all(&pool)
+        .await;
     match fetch {
-        Ok(ok) => match ok {
-            Some(_) => StatusCode::BAD_REQUEST,
-            None => {
-                let add_date = Utc::now();
-                let insert = sqlx::query("INSERT INTO public.plates_hashtag(plates_id, hashtag_id, add_date, unique_text) VALUES ($1, $2, $3, $4)")
-                    .bind(payload.plates_id)
-                    .bind(payload.hashtag_id)
-                    .bind(add_date)
-                    .bind(&unique_text)
-                    .fetch_optional(&pool)
-                    .await;
-                match insert {
-                    Ok(ok) => match ok {
-                        Some(_) => StatusCode::OK,
-                        None => StatusCode::BAD_REQUEST,
-                    },
-                    Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
-                }
-            }
-        },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn query_hashtag_plates(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<HashtagPlatesFilter>,
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        hashtag: Some(payload.tag),
+        sort_by: payload.sort_by,
+        limit: payload.limit,
+        offset: payload.offset,
+        ..PlatesSearch::default()
+    };
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
+}
+
+pub async fn query_hashtag_suggestion(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<HashtagFilter>,
+) -> Result<Json<Vec<HashtagCount>>, ApiError> {
+    let tag = normalize_tag(&payload.search_text)?;
+    let fetch: Result<Vec<HashtagCount>, sqlx::Error> = sqlx::query_as("SELECT hashtag.hashtag_id, hashtag.tag, COUNT(plates_hashtag.plates_hashtag_id) AS plates_count FROM public.hashtag LEFT JOIN public.plates_hashtag ON plates_hashtag.hashtag_id = hashtag.hashtag_id WHERE hashtag.tag LIKE $1 GROUP BY hashtag.hashtag_id ORDER BY plates_count DESC, hashtag.tag ASC LIMIT $2")
+        .bind(format!("{}%", escape_like(&tag)))
+        .bind(payload.limit.clamp(1, MAX_PAGE))
+        .fetch_all(&pool)
+        .await;
+    match fetch {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+// plates_count is the number of plates tagged within the last days
+pub async fn query_trending_hashtag(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<TrendingFilter>,
+) -> Result<Json<Vec<HashtagCount>>, StatusCode> {
+    let since = Utc::now() - Duration::days(payload.days.clamp(1, TRENDING_MAX_DAY));
+    let fetch: Result<Vec<HashtagCount>, sqlx::Error> = sqlx::query_as("SELECT hashtag.hashtag_id, hashtag.tag, COUNT(plates_hashtag.plates_hashtag_id) AS plates_count FROM public.plates_hashtag INNER JOIN public.hashtag ON hashtag.hashtag_id = plates_hashtag.hashtag_id INNER JOIN public.plates ON plates.plates_id = plates_hashtag.plates_id WHERE plates_hashtag.add_date >= $1 AND plates.is_selling IS TRUE GROUP BY hashtag.hashtag_id ORDER BY plates_count DESC, hashtag.tag ASC LIMIT $2")
+        .bind(since)
+        .bind(payload.limit.clamp(1, MAX_PAGE))
+        .fetch_all(&pool)
+        .await;
+    match fetch {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(tag: &str) -> String {
+        normalize_tag(tag).unwrap()
+    }
+
+    #[test]
+    fn folds_decomposed_sara_am() {
+        assert_eq!(normalized("\u{0E19}\u{0E4D}\u{0E32}"), "\u{0E19}\u{0E33}");
+        assert_eq!(normalized("\u{0E19}\u{0E33}"), "\u{0E19}\u{0E33}");
+        assert_eq!(
+            normalized("เลข\u{0E2A}\u{0E27}\u{0E22}\u{0E19}\u{0E4D}\u{0E32}โชค"),
+            "เลขสวยนำโชค"
+        );
+    }
+
+    #[test]
+    fn strips_zero_width_characters() {
+        assert_eq!(normalized("lucky\u{200B}number"), "luckynumber");
+        assert_eq!(normalized("\u{FEFF}เลข\u{200C}สวย\u{200D}"), "เลขสวย");
+    }
+
+    #[test]
+    fn joins_whitespace_with_underscore() {
+        assert_eq!(normalized("  Lucky   Number\t789 "), "lucky_number_789");
+        assert_eq!(normalized("เลข สวย"), "เลข_สวย");
+    }
+
+    #[test]
+    fn trims_leading_hash() {
+        assert_eq!(normalized("#Lucky"), "lucky");
+        assert_eq!(normalized(" ##เลขสวย"), "เลขสวย");
+    }
+
+    #[test]
+    fn rejects_empty_long_and_punctuation() {
+        assert!(normalize_tag("#").is_err());
+        assert!(normalize_tag("\u{200B} ").is_err());
+        assert!(normalize_tag(&"ก".repeat(TAG_MAX_CHARS + 1)).is_err());
+        assert!(normalize_tag(&"ก".repeat(TAG_MAX_CHARS)).is_ok());
+        assert!(normalize_tag("lucky-number").is_err());
+        assert!(normalize_tag("a'b").is_err());
     }
 }
@@ -1,14 +1,25 @@
+pub mod api_client;
 pub mod app_state;
 pub mod authentication;
+pub mod config;
 pub mod constants;
+pub mod email_change;
+pub mod error;
 pub mod hashtag;
 pub mod mailer;
 pub mod middleware;
+pub mod password;
 pub mod pattern;
+pub mod plate_number;
 pub mod plates;
+pub mod price;
 pub mod profile;
 pub mod query;
+pub mod query_builder;
+pub mod rate_limit;
 pub mod rating;
+pub mod role;
 pub mod s3_operations;
+pub mod session;
 pub mod shutdown;
 pub mod transfer;
@@ -1,31 +1,64 @@
-use crate::constants::{EMAIL, MINUTES, PASSWORD};
+use crate::{config::Config, constants::MINUTES};
 use lettre::{
     message::header::ContentType,
     transport::smtp::{authentication::Credentials, response::Response, Error},
     Message, SmtpTransport, Transport,
 };
 
-pub fn send_email(email: &str, reference: i32, code: i32) -> Result<Response, Error> {
-    let creds = Credentials::new(EMAIL.to_string(), PASSWORD.to_string());
-    let mailer = SmtpTransport::relay("smtp.gmail.com")
-        .unwrap()
+fn send(config: &Config, email: &str, subject: String, body: String) -> Result<Response, Error> {
+    let creds = Credentials::new(config.smtp_email.clone(), config.smtp_password.clone());
+    let mailer = SmtpTransport::relay(&config.smtp_relay)?
         .credentials(creds)
         .build();
 
     let message = Message::builder()
-        .from(EMAIL.parse().unwrap())
+        .from(config.smtp_email.parse().unwrap())
         .to(email.parse().unwrap())
-        .subject(format!(
-            "Verification code from TB789, reference: {reference}"
-        ))
+        .subject(subject)
         .header(ContentType::TEXT_HTML)
-        .body(format!(
+        .body(body)
+        .unwrap();
+
+    mailer.send(&message)
+}
+
+pub fn send_email(
+    config: &Config,
+    email: &str,
+    reference: i32,
+    code: i32,
+) -> Result<Response, Error> {
+    send(
+        config,
+        email,
+        format!("Verification code from TB789, reference: {reference}"),
+        format!(
             "<p style=\"text-align: center\">This code will expire in {MINUTES} minutes</p>
 <p style=\"text-align: center\">Your verification code is:</p>
 <h1 style=\"text-align: center; padding: 100px\">{code}</h1>
 <p style=\"text-align: center\">please don\'t reply to this email</p>"
-        ))
-        .unwrap();
+        ),
+    )
+}
 
-    mailer.send(&message)
+// sent to the previous email once the account moved to new_email
+pub fn send_email_change_notice(
+    config: &Config,
+    email: &str,
+    new_email: &str,
+    cancel_url: &str,
+) -> Result<Response, Error> {
+    // a quoted local part may carry markup
+    let new_email = new_email.replace('<', "&lt;").replace('>', "&gt;");
+    send(
+        config,
+        email,
+        "Your TB789 email was changed".to_string(),
+        format!(
+            "<p style=\"text-align: center\">The email of your TB789 account was changed to {new_email}</p>
+<p style=\"text-align: center\">If you did not make this change, cancel it and sign out every device:</p>
+<p style=\"text-align: center; padding: 50px\"><a href=\"{cancel_url}\">Cancel the email change</a></p>
+<p style=\"text-align: center\">please don\'t reply to this email</p>"
+        ),
+    )
 }
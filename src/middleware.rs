@@ -1,14 +1,18 @@
 use crate::{
+    api_client::{use_key, API_KEY_HEADER},
     app_state::AppState,
-    authentication::{Authentication, Claims},
-    constants::{ACCESS_TOKEN_KEY, API_KEY, LIMIT},
+    authentication::{AuthUser, Authentication, Claims},
+    constants::LIMIT,
+    role::Role,
+    session::{is_session_active, AuthSession},
 };
 use axum::{
     body::to_bytes,
-    extract::{Query, Request, State},
+    extract::{Request, State},
+    http::HeaderMap,
     middleware::Next,
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
@@ -16,24 +20,32 @@ use axum_extra::{
 };
 use email_address::EmailAddress;
 use hyper::StatusCode;
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use std::collections::HashMap;
+use jsonwebtoken::{decode, DecodingKey, TokenData, Validation};
 
+// the key of a registered api client is sent in the x-api-key header, every
+// accepted request is counted against the key
 pub async fn validate_api_key(
-    Query(params): Query<HashMap<String, String>>,
+    State(AppState { pool, api_keys, .. }): State<AppState>,
+    headers: HeaderMap,
     request: Request,
     next: Next,
 ) -> Result<impl IntoResponse, StatusCode> {
-    match params.get("api_key") {
-        Some(api_key) => {
-            if api_key == API_KEY {
+    let api_key = match headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(ok) => ok,
+        None => return Err(StatusCode::UNAUTHORIZED),
+    };
+    match use_key(&pool, &api_keys, api_key).await {
+        Ok(ok) => match ok {
+            Some(_) => {
                 let response = next.run(request).await;
                 Ok(response)
-            } else {
-                Err(StatusCode::BAD_REQUEST)
             }
-        }
-        None => Err(StatusCode::BAD_REQUEST),
+            None => Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
@@ -64,7 +76,7 @@ pub async fn validate_email(request: Request, next: Next) -> Result<impl IntoRes
 }
 
 pub async fn validate_email_unique(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
     request: Request,
     next: Next,
 ) -> Result<impl IntoResponse, StatusCode> {
@@ -100,20 +112,49 @@ pub async fn validate_email_unique(
 }
 
 pub async fn validate_token(
+    State(AppState { pool, config, .. }): State<AppState>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<impl IntoResponse, StatusCode> {
     let token = decode::<Claims>(
         bearer.token(),
-        &DecodingKey::from_secret(ACCESS_TOKEN_KEY.as_ref()),
+        &DecodingKey::from_secret(config.access_token_key.as_ref()),
         &Validation::default(),
     );
     match token {
-        Ok(_) => {
-            let response = next.run(request).await;
-            Ok(response)
-        }
+        Ok(TokenData { header: _, claims }) => match claims.sub.parse::<i32>() {
+            Ok(users_id) => match is_session_active(&pool, claims.sid, users_id).await {
+                Ok(true) => {
+                    request.extensions_mut().insert(AuthUser { users_id });
+                    request.extensions_mut().insert(AuthSession {
+                        sessions_id: claims.sid,
+                    });
+                    request.extensions_mut().insert(claims.role);
+                    let response = next.run(request).await;
+                    Ok(response)
+                }
+                Ok(false) => Err(StatusCode::UNAUTHORIZED),
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            },
+            Err(_) => Err(StatusCode::UNAUTHORIZED),
+        },
         Err(_) => Err(StatusCode::UNAUTHORIZED),
     }
 }
+
+// must be layered inside validate_token, which provides the role of the
+// token, the state is the lowest role allowed through
+pub async fn require_role(
+    State(required): State<Role>,
+    Extension(role): Extension<Role>,
+    request: Request,
+    next: Next,
+) -> Result<impl IntoResponse, StatusCode> {
+    if role >= required {
+        let response = next.run(request).await;
+        Ok(response)
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
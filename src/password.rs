@@ -0,0 +1,71 @@
+use argon2::{
+    password_hash::{
+        rand_core::OsRng, Error, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use std::sync::LazyLock;
+use tokio::task::spawn_blocking;
+
+pub enum Verified {
+    Valid,
+    ValidLegacy,
+    Invalid,
+}
+
+// verified against when the email is unknown so sign in takes as long as for a
+// real account
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_blocking("dummy password").unwrap_or_default());
+
+fn hash_blocking(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+fn verify_blocking(password: &str, stored: &str) -> Verified {
+    if stored.starts_with("$argon2") {
+        match PasswordHash::new(stored) {
+            Ok(parsed) => match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+                Ok(_) => Verified::Valid,
+                Err(_) => Verified::Invalid,
+            },
+            Err(_) => Verified::Invalid,
+        }
+    } else {
+        // legacy unsalted blake3 hex digest, upgraded on next sign in
+        match blake3::Hash::from_hex(stored) {
+            Ok(legacy) => {
+                if legacy == blake3::hash(password.as_bytes()) {
+                    Verified::ValidLegacy
+                } else {
+                    Verified::Invalid
+                }
+            }
+            Err(_) => Verified::Invalid,
+        }
+    }
+}
+
+// argon2 is cpu bound, it runs on the blocking pool so a burst of sign ins does
+// not stall the async workers
+pub async fn hash_password(password: &str) -> Result<String, Error> {
+    let password = password.to_string();
+    match spawn_blocking(move || hash_blocking(&password)).await {
+        Ok(ok) => ok,
+        Err(_) => Err(Error::Crypto),
+    }
+}
+
+pub async fn verify_password(password: &str, stored: &str) -> Verified {
+    let (password, stored) = (password.to_string(), stored.to_string());
+    spawn_blocking(move || verify_blocking(&password, &stored))
+        .await
+        .unwrap_or(Verified::Invalid)
+}
+
+pub async fn verify_unknown(password: &str) {
+    let password = password.to_string();
+    let _ = spawn_blocking(move || verify_blocking(&password, &DUMMY_HASH)).await;
+}
@@ -1,666 +1,454 @@
+use crate::{app_state::AppState, error::ApiError, plate_number::parse_shape};
+use axum::{extract::State, Json};
 use chrono::{DateTime, Utc};
-use sqlx::{Pool, Postgres};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{types, Pool, Postgres, Transaction};
+use std::time::Duration;
+
+// every condition that is set must hold, unset conditions are ignored
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    pub back_number_list: Option<Vec<i32>>,
+    pub back_number_shape: Option<String>,
+    pub front_text: Option<String>,
+    pub front_text_prefix: Option<String>,
+    pub front_number: Option<i32>,
+    pub vehicle_type_id_list: Option<Vec<i32>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Pattern {
+    pub pattern_id: i32,
+    pub name: String,
+    pub rule: types::Json<Rule>,
+    pub is_active: bool,
+}
+
+impl Rule {
+    pub fn matches(
+        &self,
+        front_text: &str,
+        front_number: i32,
+        back_number: i32,
+        vehicle_type_id: i32,
+    ) -> bool {
+        if back_number <= 0 {
+            return false;
+        }
+        if let Some(list) = &self.back_number_list {
+            if !list.contains(&back_number) {
+                return false;
+            }
+        }
+        if let Some(shape) = &self.back_number_shape {
+            if !parse_shape(shape).is_ok_and(|shape| shape.matches(back_number)) {
+                return false;
+            }
+        }
+        if let Some(text) = &self.front_text {
+            if front_text != text {
+                return false;
+            }
+        }
+        if let Some(prefix) = &self.front_text_prefix {
+            if !front_text.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if let Some(number) = self.front_number {
+            if front_number != number {
+                return false;
+            }
+        }
+        if let Some(list) = &self.vehicle_type_id_list {
+            if !list.contains(&vehicle_type_id) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub async fn fetch_active_patterns(pool: &Pool<Postgres>) -> Result<Vec<Pattern>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT pattern_id, name, rule, is_active FROM public.pattern WHERE is_active IS TRUE",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub fn matched_patterns(
+    patterns: &[Pattern],
+    front_text: &str,
+    front_number: i32,
+    back_number: i32,
+    vehicle_type_id: i32,
+) -> Vec<i32> {
+    patterns
+        .iter()
+        .filter(|pattern| {
+            pattern
+                .rule
+                .matches(front_text, front_number, back_number, vehicle_type_id)
+        })
+        .map(|pattern| pattern.pattern_id)
+        .collect()
+}
+
+pub async fn insert_plates_pattern(
+    plates_id: i32,
+    pattern_id_list: &[i32],
+    add_date: DateTime<Utc>,
+    pool: &Pool<Postgres>,
+) -> Result<(), sqlx::Error> {
+    if pattern_id_list.is_empty() {
+        return Ok(());
+    }
+    sqlx::query("INSERT INTO public.plates_pattern(plates_id, pattern_id, add_date) SELECT $1, UNNEST($2::INTEGER[]), $3 ON CONFLICT (plates_id, pattern_id) DO NOTHING")
+        .bind(plates_id)
+        .bind(pattern_id_list)
+        .bind(add_date)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
 
 pub async fn analyze_pattern(
     plates_id: i32,
-    front_text: &String,
+    front_text: &str,
     front_number: i32,
     back_number: i32,
     add_date: DateTime<Utc>,
     vehicle_type_id: i32,
     pool: &Pool<Postgres>,
 ) {
-    // constants
-    // pattern_168
-    if back_number == 168 {
-        let _ = sqlx::query("INSERT INTO public.pattern_168(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_789
-    if back_number == 789 {
-        let _ = sqlx::query("INSERT INTO public.pattern_789(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_289
-    if back_number == 289 {
-        let _ = sqlx::query("INSERT INTO public.pattern_289(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_456
-    if back_number == 456 {
-        let _ = sqlx::query("INSERT INTO public.pattern_456(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_911
-    if back_number == 911 {
-        let _ = sqlx::query("INSERT INTO public.pattern_911(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_718
-    if back_number == 718 {
-        let _ = sqlx::query("INSERT INTO public.pattern_718(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
+    if let Ok(patterns) = fetch_active_patterns(pool).await {
+        let matched = matched_patterns(
+            &patterns,
+            front_text,
+            front_number,
+            back_number,
+            vehicle_type_id,
+        );
+        let _ = insert_plates_pattern(plates_id, &matched, add_date, pool).await;
     }
-    // pattern_992
-    if back_number == 992 {
-        let _ = sqlx::query("INSERT INTO public.pattern_992(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_35
-    if back_number == 35 {
-        let _ = sqlx::query("INSERT INTO public.pattern_35(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_488
-    if back_number == 488 {
-        let _ = sqlx::query("INSERT INTO public.pattern_488(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_9
-    if back_number == 9 {
-        let _ = sqlx::query("INSERT INTO public.pattern_9(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_99
-    if back_number == 99 {
-        let _ = sqlx::query("INSERT INTO public.pattern_99(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_999
-    if back_number == 999 {
-        let _ = sqlx::query("INSERT INTO public.pattern_999(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_9999
-    if back_number == 9999 {
-        let _ = sqlx::query("INSERT INTO public.pattern_9999(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_7
-    if back_number == 7 {
-        let _ = sqlx::query("INSERT INTO public.pattern_7(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_77
-    if back_number == 77 {
-        let _ = sqlx::query("INSERT INTO public.pattern_77(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_777
-    if back_number == 777 {
-        let _ = sqlx::query("INSERT INTO public.pattern_777(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_7777
-    if back_number == 7777 {
-        let _ = sqlx::query("INSERT INTO public.pattern_7777(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_5
-    if back_number == 5 {
-        let _ = sqlx::query("INSERT INTO public.pattern_5(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_55
-    if back_number == 55 {
-        let _ = sqlx::query("INSERT INTO public.pattern_55(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_555
-    if back_number == 555 {
-        let _ = sqlx::query("INSERT INTO public.pattern_555(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_5555
-    if back_number == 5555 {
-        let _ = sqlx::query("INSERT INTO public.pattern_5555(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_8
-    if back_number == 8 {
-        let _ = sqlx::query("INSERT INTO public.pattern_8(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_88
-    if back_number == 88 {
-        let _ = sqlx::query("INSERT INTO public.pattern_88(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_888
-    if back_number == 888 {
-        let _ = sqlx::query("INSERT INTO public.pattern_888(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_8888
-    if back_number == 8888 {
-        let _ = sqlx::query("INSERT INTO public.pattern_8888(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_1
-    if back_number == 1 {
-        let _ = sqlx::query("INSERT INTO public.pattern_1(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_599
-    if back_number == 599 {
-        let _ = sqlx::query("INSERT INTO public.pattern_599(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_595
-    if back_number == 595 {
-        let _ = sqlx::query("INSERT INTO public.pattern_595(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_959
-    if back_number == 959 {
-        let _ = sqlx::query("INSERT INTO public.pattern_959(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_955
-    if back_number == 955 {
-        let _ = sqlx::query("INSERT INTO public.pattern_955(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_5959
-    if back_number == 5959 {
-        let _ = sqlx::query("INSERT INTO public.pattern_5959(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_9595
-    if back_number == 9595 {
-        let _ = sqlx::query("INSERT INTO public.pattern_9595(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_5599
-    if back_number == 5599 {
-        let _ = sqlx::query("INSERT INTO public.pattern_5599(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_9955
-    if back_number == 9955 {
-        let _ = sqlx::query("INSERT INTO public.pattern_9955(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_5995
-    if back_number == 5995 {
-        let _ = sqlx::query("INSERT INTO public.pattern_5995(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_9559
-    if back_number == 9559 {
-        let _ = sqlx::query("INSERT INTO public.pattern_9559(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // variable
-    // pattern_x
-    if back_number > 0 && back_number < 10 {
-        let _ = sqlx::query("INSERT INTO public.pattern_x(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_xx
-    if back_number == 11
-        || back_number == 22
-        || back_number == 33
-        || back_number == 44
-        || back_number == 55
-        || back_number == 66
-        || back_number == 77
-        || back_number == 88
-        || back_number == 99
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_xx(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_xxx
-    if back_number == 111
-        || back_number == 222
-        || back_number == 333
-        || back_number == 444
-        || back_number == 555
-        || back_number == 666
-        || back_number == 777
-        || back_number == 888
-        || back_number == 999
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_xxx(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_xxxx
-    if back_number == 1111
-        || back_number == 2222
-        || back_number == 3333
-        || back_number == 4444
-        || back_number == 5555
-        || back_number == 6666
-        || back_number == 7777
-        || back_number == 8888
-        || back_number == 9999
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_xxxx(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_xy
-    if back_number > 9 && back_number < 100 {
-        let list: Vec<char> = back_number.to_string().chars().collect();
-        let a = list[0];
-        let b = list[1];
-        if a != b {
-            let _ =
-                sqlx::query("INSERT INTO public.pattern_xy(plates_id, add_date) VALUES ($1, $2)")
-                    .bind(plates_id)
-                    .bind(add_date)
-                    .execute(pool)
-                    .await;
-        }
-    }
-    // pattern_xyy
-    if back_number > 99 && back_number < 1000 {
-        let list: Vec<char> = back_number.to_string().chars().collect();
-        let a = list[0];
-        let b = list[1];
-        let c = list[2];
-        if a != b && b == c {
-            let _ =
-                sqlx::query("INSERT INTO public.pattern_xyy(plates_id, add_date) VALUES ($1, $2)")
-                    .bind(plates_id)
-                    .bind(add_date)
-                    .execute(pool)
-                    .await;
+}
+
+const BACKFILL_BATCH: i64 = 1000;
+const BACKFILL_IDLE: Duration = Duration::from_secs(5);
+const SAMPLE_SIZE: usize = 20;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PatternSummary {
+    pub pattern_id: i32,
+    pub name: String,
+    pub rule: types::Json<Rule>,
+    pub is_active: bool,
+    pub add_date: String,
+    pub matched_count: i64,
+    pub backfill_status: String,
+    pub backfill_after: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DryRunPattern {
+    pub rule: Rule,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PatternMatch {
+    pub matched_count: i64,
+    pub sample_plates_id_list: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublishPattern {
+    pub name: String,
+    pub rule: Rule,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublishedPattern {
+    pub pattern_id: i32,
+    pub backfill_status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PatternIsActive {
+    pub pattern_id: i32,
+    pub is_active: bool,
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let invalid = |message: &str| {
+            Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_rule",
+                message,
+            ))
+        };
+        if self.back_number_list.is_none()
+            && self.back_number_shape.is_none()
+            && self.front_text.is_none()
+            && self.front_text_prefix.is_none()
+        {
+            return invalid("rule needs a back number or front text condition");
         }
-    }
-    // pattern_xyyy
-    if back_number > 999 && back_number < 10000 {
-        let list: Vec<char> = back_number.to_string().chars().collect();
-        let a = list[0];
-        let b = list[1];
-        let c = list[2];
-        let d = list[3];
-        if a != b && b == c && c == d {
-            let _ =
-                sqlx::query("INSERT INTO public.pattern_xyyy(plates_id, add_date) VALUES ($1, $2)")
-                    .bind(plates_id)
-                    .bind(add_date)
-                    .execute(pool)
-                    .await;
+        if let Some(list) = &self.back_number_list {
+            if list.is_empty() || list.iter().any(|number| *number < 1 || *number > 9999) {
+                return invalid("back_number_list must hold numbers between 1 and 9999");
+            }
         }
-    }
-    // pattern_xxyy
-    if back_number > 999 && back_number < 10000 {
-        let list: Vec<char> = back_number.to_string().chars().collect();
-        let a = list[0];
-        let b = list[1];
-        let c = list[2];
-        let d = list[3];
-        if a == b && c == d && a != c {
-            let _ =
-                sqlx::query("INSERT INTO public.pattern_xxyy(plates_id, add_date) VALUES ($1, $2)")
-                    .bind(plates_id)
-                    .bind(add_date)
-                    .execute(pool)
-                    .await;
+        if let Some(shape) = &self.back_number_shape {
+            if parse_shape(shape).is_err() {
+                return invalid("back_number_shape must be ascending, descending, palindrome or 1 to 4 of 0-9, ? and letters");
+            }
         }
-    }
-    // pattern_xyxy
-    if back_number > 999 && back_number < 10000 {
-        let list: Vec<char> = back_number.to_string().chars().collect();
-        let a = list[0];
-        let b = list[1];
-        let c = list[2];
-        let d = list[3];
-        if a == c && b == d && a != b {
-            let _ =
-                sqlx::query("INSERT INTO public.pattern_xyxy(plates_id, add_date) VALUES ($1, $2)")
-                    .bind(plates_id)
-                    .bind(add_date)
-                    .execute(pool)
-                    .await;
+        if let Some(prefix) = &self.front_text_prefix {
+            if prefix.is_empty() {
+                return invalid("front_text_prefix must not be empty");
+            }
         }
+        Ok(())
     }
-    // pattern_xyyx
-    if back_number > 999 && back_number < 10000 {
-        let list: Vec<char> = back_number.to_string().chars().collect();
-        let a = list[0];
-        let b = list[1];
-        let c = list[2];
-        let d = list[3];
-        if a == d && b == c && a != b {
-            let _ =
-                sqlx::query("INSERT INTO public.pattern_xyyx(plates_id, add_date) VALUES ($1, $2)")
-                    .bind(plates_id)
-                    .bind(add_date)
-                    .execute(pool)
-                    .await;
+}
+
+type PlatesRow = (i32, String, i32, i32, i32);
+
+fn matched_plates(rule: &Rule, rows: &[PlatesRow]) -> Vec<i32> {
+    rows.iter()
+        .filter(
+            |(_, front_text, front_number, back_number, vehicle_type_id)| {
+                rule.matches(front_text, *front_number, *back_number, *vehicle_type_id)
+            },
+        )
+        .map(|(plates_id, ..)| *plates_id)
+        .collect()
+}
+
+// walks public.plates in batches of BACKFILL_BATCH by plates_id without writing
+async fn scan_plates(pool: &Pool<Postgres>, rule: &Rule) -> Result<PatternMatch, sqlx::Error> {
+    let mut last_plates_id = 0;
+    let mut result = PatternMatch {
+        matched_count: 0,
+        sample_plates_id_list: Vec::new(),
+    };
+    loop {
+        let rows: Vec<PlatesRow> = sqlx::query_as("SELECT plates_id, front_text, front_number, back_number, vehicle_type_id FROM public.plates WHERE plates_id > $1 ORDER BY plates_id ASC LIMIT $2")
+            .bind(last_plates_id)
+            .bind(BACKFILL_BATCH)
+            .fetch_all(pool)
+            .await?;
+        let Some((plates_id, ..)) = rows.last() else {
+            break;
+        };
+        last_plates_id = *plates_id;
+        let matched = matched_plates(rule, &rows);
+        result.matched_count += matched.len() as i64;
+        let room = SAMPLE_SIZE.saturating_sub(result.sample_plates_id_list.len());
+        result
+            .sample_plates_id_list
+            .extend(matched.into_iter().take(room));
+        if (rows.len() as i64) < BACKFILL_BATCH {
+            break;
         }
     }
-    // pattern_xyx
-    if back_number > 99 && back_number < 1000 {
-        let list: Vec<char> = back_number.to_string().chars().collect();
-        let a = list[0];
-        let b = list[1];
-        let c = list[2];
-        if a != b && a == c {
-            let _ =
-                sqlx::query("INSERT INTO public.pattern_xyx(plates_id, add_date) VALUES ($1, $2)")
-                    .bind(plates_id)
-                    .bind(add_date)
-                    .execute(pool)
-                    .await;
+    Ok(result)
+}
+
+// scans the next batch of the oldest pending active pattern into the staging
+// table, the batch that reaches the last plates swaps the staged matches into
+// plates_pattern in the same transaction, false when nothing is pending
+async fn backfill_batch(pool: &Pool<Postgres>) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let pending: Option<(i32, types::Json<Rule>, i32)> = sqlx::query_as("SELECT pattern_id, rule, backfill_after FROM public.pattern WHERE (backfill_status = 'pending' AND is_active IS TRUE) ORDER BY pattern_id ASC LIMIT 1 FOR UPDATE SKIP LOCKED")
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some((pattern_id, types::Json(rule), backfill_after)) = pending else {
+        return Ok(false);
+    };
+    let rows: Vec<PlatesRow> = sqlx::query_as("SELECT plates_id, front_text, front_number, back_number, vehicle_type_id FROM public.plates WHERE plates_id > $1 ORDER BY plates_id ASC LIMIT $2")
+        .bind(backfill_after)
+        .bind(BACKFILL_BATCH)
+        .fetch_all(&mut *tx)
+        .await?;
+    let last_plates_id = rows
+        .last()
+        .map(|(plates_id, ..)| *plates_id)
+        .unwrap_or(backfill_after);
+    sqlx::query("INSERT INTO public.plates_pattern_staging(pattern_id, plates_id) SELECT $1, UNNEST($2::INTEGER[]) ON CONFLICT (pattern_id, plates_id) DO NOTHING")
+        .bind(pattern_id)
+        .bind(matched_plates(&rule, &rows))
+        .execute(&mut *tx)
+        .await?;
+    if (rows.len() as i64) < BACKFILL_BATCH {
+        // plates added after the scan already have their live row from
+        // analyze_pattern and are left alone
+        sqlx::query("DELETE FROM public.plates_pattern WHERE (pattern_id = $1 AND plates_id <= $2 AND plates_id NOT IN (SELECT plates_id FROM public.plates_pattern_staging WHERE pattern_id = $1))")
+            .bind(pattern_id)
+            .bind(last_plates_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO public.plates_pattern(plates_id, pattern_id, add_date) SELECT plates_id, pattern_id, $2 FROM public.plates_pattern_staging WHERE pattern_id = $1 ON CONFLICT (plates_id, pattern_id) DO NOTHING")
+            .bind(pattern_id)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM public.plates_pattern_staging WHERE pattern_id = $1")
+            .bind(pattern_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query(
+        "UPDATE public.pattern SET backfill_after = $1, backfill_status = $2 WHERE pattern_id = $3",
+    )
+    .bind(last_plates_id)
+    .bind(if (rows.len() as i64) < BACKFILL_BATCH {
+        "done"
+    } else {
+        "pending"
+    })
+    .bind(pattern_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+// spawned once at startup, several servers can run it side by side since a
+// pattern is locked with SKIP LOCKED while one of its batches runs
+pub async fn run_backfill(pool: Pool<Postgres>) {
+    loop {
+        match backfill_batch(&pool).await {
+            Ok(true) => (),
+            Ok(false) => tokio::time::sleep(BACKFILL_IDLE).await,
+            Err(err) => {
+                tracing::warn!("pattern backfill failed: {err}");
+                tokio::time::sleep(BACKFILL_IDLE).await;
+            }
         }
     }
-    // pattern_xyz
-    if back_number == 123
-        || back_number == 234
-        || back_number == 345
-        || back_number == 456
-        || back_number == 567
-        || back_number == 678
-        || back_number == 789
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_xyz(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_zyx
-    if back_number == 987
-        || back_number == 876
-        || back_number == 765
-        || back_number == 654
-        || back_number == 543
-        || back_number == 432
-        || back_number == 321
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_zyx(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_wxyz
-    if back_number == 1234
-        || back_number == 2345
-        || back_number == 3456
-        || back_number == 4567
-        || back_number == 5678
-        || back_number == 6789
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_wxyz(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_zyxw
-    if back_number == 9876
-        || back_number == 8765
-        || back_number == 7654
-        || back_number == 6543
-        || back_number == 5432
-        || back_number == 4321
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_zyxw(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_x00
-    if back_number == 100
-        || back_number == 200
-        || back_number == 300
-        || back_number == 400
-        || back_number == 500
-        || back_number == 600
-        || back_number == 700
-        || back_number == 800
-        || back_number == 900
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_x00(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_x000
-    if back_number == 1000
-        || back_number == 2000
-        || back_number == 3000
-        || back_number == 4000
-        || back_number == 5000
-        || back_number == 6000
-        || back_number == 7000
-        || back_number == 8000
-        || back_number == 9000
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_x000(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_x99
-    if back_number == 199
-        || back_number == 299
-        || back_number == 399
-        || back_number == 499
-        || back_number == 599
-        || back_number == 699
-        || back_number == 799
-        || back_number == 899
-        || back_number == 999
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_x99(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_x999
-    if back_number == 1999
-        || back_number == 2999
-        || back_number == 3999
-        || back_number == 4999
-        || back_number == 5999
-        || back_number == 6999
-        || back_number == 7999
-        || back_number == 8999
-        || back_number == 9999
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_x999(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
+}
+
+// restarts the back-fill of pattern_id from the first plates
+async fn queue_backfill(
+    tx: &mut Transaction<'static, Postgres>,
+    pattern_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM public.plates_pattern_staging WHERE pattern_id = $1")
+        .bind(pattern_id)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE public.pattern SET backfill_status = 'pending', backfill_after = 0 WHERE pattern_id = $1")
+        .bind(pattern_id)
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+}
+
+pub async fn list_pattern(
+    State(AppState { pool, .. }): State<AppState>,
+) -> Result<Json<Vec<PatternSummary>>, StatusCode> {
+    let fetch: Result<Vec<PatternSummary>, sqlx::Error> = sqlx::query_as("SELECT pattern.pattern_id, pattern.name, pattern.rule, pattern.is_active, pattern.add_date::TEXT, COUNT(plates_pattern.plates_pattern_id) AS matched_count, pattern.backfill_status, pattern.backfill_after FROM public.pattern LEFT JOIN public.plates_pattern ON plates_pattern.pattern_id = pattern.pattern_id GROUP BY pattern.pattern_id ORDER BY pattern.pattern_id ASC")
+        .fetch_all(&pool)
+        .await;
+    match fetch {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
-    // pattern_x55
-    if back_number == 155
-        || back_number == 255
-        || back_number == 355
-        || back_number == 455
-        || back_number == 555
-        || back_number == 655
-        || back_number == 755
-        || back_number == 855
-        || back_number == 955
-    {
-        let _ = sqlx::query("INSERT INTO public.pattern_x55(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
+}
+
+pub async fn dry_run_pattern(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<DryRunPattern>,
+) -> Result<Json<PatternMatch>, ApiError> {
+    payload.rule.validate()?;
+    match scan_plates(&pool, &payload.rule).await {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
-    // pattern_x555
-    if back_number == 1555
-        || back_number == 2555
-        || back_number == 3555
-        || back_number == 4555
-        || back_number == 5555
-        || back_number == 6555
-        || back_number == 7555
-        || back_number == 8555
-        || back_number == 9555
+}
+
+// creates the pattern or replaces the rule of the pattern with the same name
+// and queues its back-fill, the previous matches stay live until it completes
+pub async fn publish_pattern(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<PublishPattern>,
+) -> Result<Json<PublishedPattern>, ApiError> {
+    payload.rule.validate()?;
+    if payload.name.is_empty()
+        || !payload
+            .name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
     {
-        let _ = sqlx::query("INSERT INTO public.pattern_x555(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_rakhang
-    if front_number == 0 && vehicle_type_id == 1 && front_text.starts_with("ฆ") {
-        let _ =
-            sqlx::query("INSERT INTO public.pattern_rakhang(plates_id, add_date) VALUES ($1, $2)")
-                .bind(plates_id)
-                .bind(add_date)
-                .execute(pool)
-                .await;
-    }
-    // pattern_kob
-    if front_number == 0 && vehicle_type_id == 1 && front_text == "กบ" {
-        let _ = sqlx::query("INSERT INTO public.pattern_kob(plates_id, add_date) VALUES ($1, $2)")
-            .bind(plates_id)
-            .bind(add_date)
-            .execute(pool)
-            .await;
-    }
-    // pattern_torthan
-    if front_number == 0 && vehicle_type_id == 1 && front_text.starts_with("ฐ") {
-        let _ =
-            sqlx::query("INSERT INTO public.pattern_torthan(plates_id, add_date) VALUES ($1, $2)")
-                .bind(plates_id)
-                .bind(add_date)
-                .execute(pool)
-                .await;
-    }
-    // pattern_korkai_korkai
-    if front_number == 0 && vehicle_type_id == 1 && front_text == "กก" {
-        let _ = sqlx::query(
-            "INSERT INTO public.pattern_korkai_korkai(plates_id, add_date) VALUES ($1, $2)",
-        )
-        .bind(plates_id)
-        .bind(add_date)
-        .execute(pool)
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_pattern_name",
+            "name must be made of a-z, 0-9 and _",
+        ));
+    }
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let upsert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.pattern(name, rule, is_active, add_date) VALUES ($1, $2, $3, $4) ON CONFLICT (name) DO UPDATE SET rule = EXCLUDED.rule, is_active = EXCLUDED.is_active RETURNING pattern_id")
+        .bind(&payload.name)
+        .bind(types::Json(&payload.rule))
+        .bind(payload.is_active)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
         .await;
+    let pattern_id = match upsert {
+        Ok((pattern_id,)) => pattern_id,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    if queue_backfill(&mut tx, pattern_id).await.is_err() || tx.commit().await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(Json(PublishedPattern {
+        pattern_id,
+        backfill_status: "pending".to_string(),
+    }))
+}
+
+// enabling queues a fresh back-fill so plates added while the pattern was
+// disabled are picked up
+pub async fn edit_pattern_is_active(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<PatternIsActive>,
+) -> Result<StatusCode, ApiError> {
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
+        "UPDATE public.pattern SET is_active = $1 WHERE pattern_id = $2 RETURNING pattern_id",
+    )
+    .bind(payload.is_active)
+    .bind(payload.pattern_id)
+    .fetch_optional(&mut *tx)
+    .await;
+    match update {
+        Ok(ok) => match ok {
+            Some(_) => {
+                if (payload.is_active && queue_backfill(&mut tx, payload.pattern_id).await.is_err())
+                    || tx.commit().await.is_err()
+                {
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+                Ok(StatusCode::OK)
+            }
+            None => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "pattern_not_found",
+                format!("pattern {} does not exist", payload.pattern_id),
+            )),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
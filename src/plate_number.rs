@@ -0,0 +1,648 @@
+use crate::error::ApiError;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+// totals read as auspicious or inauspicious in thai plate numerology,
+// anything else is neutral
+pub const LUCKY_TOTALS: [i32; 22] = [
+    9, 14, 15, 19, 23, 24, 36, 40, 41, 42, 44, 45, 46, 50, 51, 54, 55, 56, 59, 63, 64, 65,
+];
+pub const UNLUCKY_TOTALS: [i32; 20] = [
+    7, 10, 11, 12, 13, 16, 17, 18, 21, 22, 25, 26, 27, 29, 30, 31, 33, 34, 35, 37,
+];
+pub const LUCKY_DIGITS: [char; 4] = ['5', '6', '8', '9'];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Luck {
+    Lucky,
+    #[default]
+    Neutral,
+    Unlucky,
+}
+
+pub struct PlateFormat {
+    pub front_text_chars: (usize, usize),
+    pub front_number_max: i32,
+    pub back_number_max: i32,
+}
+
+// private cars (vehicle_type_id 1) carry an optional leading digit and one or
+// two consonants, other vehicle types are only checked against the loose format
+pub fn plate_format(vehicle_type_id: i32) -> PlateFormat {
+    match vehicle_type_id {
+        1 => PlateFormat {
+            front_text_chars: (1, 2),
+            front_number_max: 9,
+            back_number_max: 9999,
+        },
+        _ => PlateFormat {
+            front_text_chars: (0, 3),
+            front_number_max: 99,
+            back_number_max: 9999,
+        },
+    }
+}
+
+pub fn consonant_value(c: char) -> Option<i32> {
+    match c {
+        'ก' | 'ด' | 'ถ' | 'ท' | 'ภ' | 'ฤ' => Some(1),
+        'ข' | 'ฃ' | 'ง' | 'ช' | 'บ' | 'ป' => Some(2),
+        'ฆ' | 'ต' | 'ฑ' | 'ฒ' => Some(3),
+        'ค' | 'ฅ' | 'ญ' | 'ธ' | 'ร' | 'ษ' => Some(4),
+        'ฉ' | 'ฌ' | 'ฎ' | 'ณ' | 'น' | 'ม' | 'ห' | 'ฬ' | 'ฮ' => Some(5),
+        'จ' | 'ล' | 'ว' | 'อ' => Some(6),
+        'ซ' | 'ศ' | 'ส' => Some(7),
+        'ผ' | 'ฝ' | 'พ' | 'ฟ' | 'ย' => Some(8),
+        'ฏ' | 'ฐ' => Some(9),
+        _ => None,
+    }
+}
+
+pub fn number_sum(number: i32) -> i32 {
+    number
+        .unsigned_abs()
+        .to_string()
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .sum::<u32>() as i32
+}
+
+// the traditional total: consonant values plus every digit of the plate
+pub fn digit_sum(front_text: &str, front_number: i32, back_number: i32) -> i32 {
+    let text: i32 = front_text.chars().filter_map(consonant_value).sum();
+    text + number_sum(front_number) + number_sum(back_number)
+}
+
+pub fn luck(total: i32) -> Luck {
+    if LUCKY_TOTALS.contains(&total) {
+        Luck::Lucky
+    } else if UNLUCKY_TOTALS.contains(&total) {
+        Luck::Unlucky
+    } else {
+        Luck::Neutral
+    }
+}
+
+pub fn luck_totals(luck: Luck) -> Vec<i32> {
+    match luck {
+        Luck::Lucky => LUCKY_TOTALS.to_vec(),
+        Luck::Unlucky => UNLUCKY_TOTALS.to_vec(),
+        Luck::Neutral => (1..=99)
+            .filter(|total| !LUCKY_TOTALS.contains(total) && !UNLUCKY_TOTALS.contains(total))
+            .collect(),
+    }
+}
+
+// 0 to 100, the total sets the base and every lucky back digit adds 10
+pub fn lucky_score(front_text: &str, front_number: i32, back_number: i32) -> i32 {
+    let base = match luck(digit_sum(front_text, front_number, back_number)) {
+        Luck::Lucky => 60,
+        Luck::Neutral => 30,
+        Luck::Unlucky => 0,
+    };
+    let bonus = back_number
+        .to_string()
+        .chars()
+        .filter(|c| LUCKY_DIGITS.contains(c))
+        .count() as i32
+        * 10;
+    (base + bonus).min(100)
+}
+
+pub fn validate(
+    front_text: &str,
+    front_number: i32,
+    back_number: i32,
+    vehicle_type_id: i32,
+) -> Result<(), ApiError> {
+    let format = plate_format(vehicle_type_id);
+    let invalid = |message: String| {
+        Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_plate_number",
+            message,
+        ))
+    };
+    let (min, max) = format.front_text_chars;
+    let count = front_text.chars().count();
+    if count < min || count > max {
+        return invalid(format!("front_text must have {min} to {max} characters"));
+    }
+    if front_text.chars().any(|c| consonant_value(c).is_none()) {
+        return invalid("front_text must only contain thai consonants".to_string());
+    }
+    if front_number < 0 || front_number > format.front_number_max {
+        return invalid(format!(
+            "front_number must be between 0 and {}",
+            format.front_number_max
+        ));
+    }
+    if back_number < 1 || back_number > format.back_number_max {
+        return invalid(format!(
+            "back_number must be between 1 and {}",
+            format.back_number_max
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackNumberShape {
+    // positions are 1 based to match SUBSTR
+    Digits {
+        like: String,
+        length: i32,
+        equal: Vec<(usize, usize)>,
+        distinct: Vec<(usize, usize)>,
+    },
+    Ascending,
+    Descending,
+    Palindrome,
+}
+
+// "ascending", "descending", "palindrome" or up to 4 of: a digit matching
+// itself, ? matching any digit, a letter standing for one digit where the same
+// letter is the same digit and different letters are different digits,
+// e.g. x999, 1?1?, AABB, ABAB
+pub fn parse_shape(shape: &str) -> Result<BackNumberShape, ApiError> {
+    let shape = shape.trim().to_lowercase();
+    match shape.as_str() {
+        "ascending" => return Ok(BackNumberShape::Ascending),
+        "descending" => return Ok(BackNumberShape::Descending),
+        "palindrome" => return Ok(BackNumberShape::Palindrome),
+        _ => (),
+    }
+    let chars: Vec<char> = shape.chars().collect();
+    let valid = chars
+        .iter()
+        .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase() || *c == '?');
+    if chars.is_empty() || chars.len() > 4 || !valid || chars[0] == '0' {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_shape",
+            "shape must be ascending, descending, palindrome or 1 to 4 of 0-9, ? and letters",
+        ));
+    }
+    let like = chars
+        .iter()
+        .map(|c| if c.is_ascii_digit() { *c } else { '_' })
+        .collect();
+    let mut first: Vec<(char, usize)> = Vec::new();
+    let mut equal = Vec::new();
+    for (i, c) in chars.iter().enumerate() {
+        if !c.is_ascii_lowercase() {
+            continue;
+        }
+        match first.iter().find(|(letter, _)| letter == c) {
+            Some((_, position)) => equal.push((*position, i + 1)),
+            None => first.push((*c, i + 1)),
+        }
+    }
+    let mut distinct = Vec::new();
+    for (i, (_, a)) in first.iter().enumerate() {
+        for (_, b) in &first[i + 1..] {
+            distinct.push((*a, *b));
+        }
+    }
+    Ok(BackNumberShape::Digits {
+        like,
+        length: chars.len() as i32,
+        equal,
+        distinct,
+    })
+}
+
+impl BackNumberShape {
+    pub fn matches(&self, back_number: i32) -> bool {
+        if !(1..=9999).contains(&back_number) {
+            return false;
+        }
+        let text = back_number.to_string();
+        match self {
+            BackNumberShape::Digits {
+                like,
+                length,
+                equal,
+                distinct,
+            } => {
+                let digits = text.as_bytes();
+                let digit = |position: &usize| digits[position - 1];
+                digits.len() == *length as usize
+                    && like.bytes().zip(digits).all(|(l, d)| l == b'_' || l == *d)
+                    && equal.iter().all(|(a, b)| digit(a) == digit(b))
+                    && distinct.iter().all(|(a, b)| digit(a) != digit(b))
+            }
+            BackNumberShape::Ascending => back_number >= 100 && "0123456789".contains(&text),
+            BackNumberShape::Descending => back_number >= 100 && "9876543210".contains(&text),
+            BackNumberShape::Palindrome => {
+                back_number >= 10 && text.chars().rev().collect::<String>() == text
+            }
+        }
+    }
+
+    // every back number the shape matches, plates carry 1 to 9999
+    pub fn back_numbers(&self) -> Vec<i32> {
+        (1..=9999).filter(|number| self.matches(*number)).collect()
+    }
+
+    // the signatures of back_numbers, few enough to look up
+    // plates.back_number_shape with its index
+    pub fn signatures(&self) -> Vec<String> {
+        let mut signatures: Vec<String> = self
+            .back_numbers()
+            .into_iter()
+            .map(shape_signature)
+            .collect();
+        signatures.sort();
+        signatures.dedup();
+        signatures
+    }
+}
+
+// the digit signature stored in plates.back_number_shape, each digit becomes
+// a letter in order of first appearance, e.g. 1212 is abab and 155 is abb,
+// migration 0016 computes the same in public.back_number_shape
+pub fn shape_signature(back_number: i32) -> String {
+    let mut seen: Vec<char> = Vec::new();
+    back_number
+        .unsigned_abs()
+        .to_string()
+        .chars()
+        .map(|digit| {
+            let index = match seen.iter().position(|c| *c == digit) {
+                Some(index) => index,
+                None => {
+                    seen.push(digit);
+                    seen.len() - 1
+                }
+            };
+            (b'a' + index as u8) as char
+        })
+        .collect()
+}
+
+const MAX_CANDIDATES: usize = 64;
+
+// latin spellings of thai plate consonants, two letter spellings are matched
+// first and the first consonant of each list is the most common reading
+const TRANSLITERATION: [(&str, &[char]); 28] = [
+    ("kh", &['ข', 'ค', 'ฆ']),
+    ("ng", &['ง']),
+    ("ch", &['ช', 'จ', 'ฉ', 'ฌ']),
+    ("th", &['ท', 'ถ', 'ธ', 'ฐ', 'ฑ', 'ฒ']),
+    ("ph", &['พ', 'ผ', 'ภ']),
+    ("k", &['ก', 'ข', 'ค']),
+    ("g", &['ก']),
+    ("c", &['จ', 'ช']),
+    ("j", &['จ']),
+    ("s", &['ส', 'ศ', 'ษ', 'ซ']),
+    ("z", &['ซ']),
+    ("d", &['ด', 'ฎ']),
+    ("t", &['ต', 'ท', 'ฏ']),
+    ("n", &['น', 'ณ']),
+    ("b", &['บ']),
+    ("p", &['ป', 'พ']),
+    ("f", &['ฟ', 'ฝ']),
+    ("m", &['ม']),
+    ("y", &['ย', 'ญ']),
+    ("r", &['ร']),
+    ("l", &['ล', 'ฬ']),
+    ("w", &['ว']),
+    ("h", &['ห', 'ฮ']),
+    ("a", &['อ']),
+    ("e", &['อ']),
+    ("i", &['อ']),
+    ("o", &['อ']),
+    ("u", &['อ']),
+];
+
+// reads front text typed in thai or latin letters into the thai consonant
+// strings it may stand for, thai vowels, tone marks, spaces and zero width
+// characters are dropped since plates only carry consonants, empty text has
+// no candidates
+pub fn front_text_candidates(text: &str) -> Result<Vec<String>, ApiError> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let mut candidates = vec![String::new()];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let options: Vec<char> = if consonant_value(c).is_some() {
+            vec![c]
+        } else if c.is_ascii_lowercase() {
+            let rest: String = chars[i..].iter().take(2).collect();
+            match TRANSLITERATION
+                .iter()
+                .find(|(latin, _)| rest.starts_with(latin))
+            {
+                // a latin vowel only spells อ at the start, elsewhere it is
+                // the unwritten vowel between consonants as in kob for กบ
+                Some((latin, _)) if i > 0 && "aeiou".contains(latin) => Vec::new(),
+                Some((latin, thai)) => {
+                    i += latin.len() - 1;
+                    thai.to_vec()
+                }
+                None => {
+                    return Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_front_text",
+                        format!("{c} does not spell a thai consonant"),
+                    ))
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        i += 1;
+        if options.is_empty() {
+            continue;
+        }
+        candidates = candidates
+            .iter()
+            .flat_map(|candidate| {
+                options.iter().map(move |option| {
+                    let mut next = candidate.clone();
+                    next.push(*option);
+                    next
+                })
+            })
+            .take(MAX_CANDIDATES)
+            .collect();
+    }
+    if candidates[0].is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_front_text",
+            "front text must contain thai consonants or their latin spelling",
+        ));
+    }
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_error(result: Result<(), ApiError>) -> &'static str {
+        result.unwrap_err().error
+    }
+
+    #[test]
+    fn digit_sum_of_known_plates() {
+        assert_eq!(digit_sum("กข", 0, 1234), 13);
+        assert_eq!(digit_sum("กก", 9, 9999), 47);
+        assert_eq!(digit_sum("ฮฮ", 1, 1), 12);
+        assert_eq!(digit_sum("ฏฐ", 0, 9), 27);
+        // characters that are not plate consonants add nothing
+        assert_eq!(digit_sum("กา", 0, 1), 2);
+    }
+
+    #[test]
+    fn private_car_validation() {
+        assert!(validate("กข", 1, 1234, 1).is_ok());
+        assert!(validate("ก", 0, 1, 1).is_ok());
+        assert_eq!(
+            invalid_error(validate("", 1, 1234, 1)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กขค", 1, 1234, 1)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กA", 1, 1234, 1)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กา", 1, 1234, 1)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กข", 10, 1234, 1)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กข", -1, 1234, 1)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กข", 1, 0, 1)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กข", 1, 10000, 1)),
+            "invalid_plate_number"
+        );
+    }
+
+    #[test]
+    fn other_vehicle_type_validation() {
+        assert!(validate("", 99, 1, 2).is_ok());
+        assert!(validate("กขค", 0, 9999, 3).is_ok());
+        assert_eq!(
+            invalid_error(validate("กขคง", 1, 1234, 2)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กข", 100, 1234, 2)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กข", 1, 0, 2)),
+            "invalid_plate_number"
+        );
+        assert_eq!(
+            invalid_error(validate("กข", 1, 10000, 2)),
+            "invalid_plate_number"
+        );
+    }
+
+    #[test]
+    fn luck_boundaries() {
+        assert_eq!(luck(0), Luck::Neutral);
+        assert_eq!(luck(7), Luck::Unlucky);
+        assert_eq!(luck(8), Luck::Neutral);
+        assert_eq!(luck(9), Luck::Lucky);
+        assert_eq!(luck(37), Luck::Unlucky);
+        assert_eq!(luck(38), Luck::Neutral);
+        assert_eq!(luck(65), Luck::Lucky);
+        assert_eq!(luck(66), Luck::Neutral);
+        let neutral = luck_totals(Luck::Neutral);
+        assert!(neutral.iter().all(|total| luck(*total) == Luck::Neutral));
+        assert_eq!(
+            neutral.len() + LUCKY_TOTALS.len() + UNLUCKY_TOTALS.len(),
+            99
+        );
+    }
+
+    #[test]
+    fn lucky_score_bounds() {
+        // 13 is unlucky and 1234 has no lucky digit
+        assert_eq!(lucky_score("กข", 0, 1234), 0);
+        // 39 is neutral with four lucky digits
+        assert_eq!(lucky_score("กข", 0, 9999), 70);
+        // 40 is lucky with four lucky digits, capped at 100
+        assert_eq!(lucky_score("ม", 0, 9989), 100);
+    }
+
+    #[test]
+    fn parse_shape_digits() {
+        assert_eq!(
+            parse_shape("x999").unwrap(),
+            BackNumberShape::Digits {
+                like: "_999".to_string(),
+                length: 4,
+                equal: vec![],
+                distinct: vec![],
+            }
+        );
+        assert_eq!(
+            parse_shape("1?1?").unwrap(),
+            BackNumberShape::Digits {
+                like: "1_1_".to_string(),
+                length: 4,
+                equal: vec![],
+                distinct: vec![],
+            }
+        );
+        assert_eq!(
+            parse_shape("AABB").unwrap(),
+            BackNumberShape::Digits {
+                like: "____".to_string(),
+                length: 4,
+                equal: vec![(1, 2), (3, 4)],
+                distinct: vec![(1, 3)],
+            }
+        );
+        assert_eq!(
+            parse_shape("ABAB").unwrap(),
+            BackNumberShape::Digits {
+                like: "____".to_string(),
+                length: 4,
+                equal: vec![(1, 3), (2, 4)],
+                distinct: vec![(1, 2)],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_shape_keywords_and_errors() {
+        assert_eq!(
+            parse_shape(" Palindrome ").unwrap(),
+            BackNumberShape::Palindrome
+        );
+        assert_eq!(
+            parse_shape("ascending").unwrap(),
+            BackNumberShape::Ascending
+        );
+        assert_eq!(
+            parse_shape("DESCENDING").unwrap(),
+            BackNumberShape::Descending
+        );
+        for shape in ["", "12345", "0abc", "1-2", "ก", "%"] {
+            assert_eq!(parse_shape(shape).unwrap_err().error, "invalid_shape");
+        }
+    }
+
+    #[test]
+    fn front_text_candidates_thai() {
+        assert_eq!(front_text_candidates("กข").unwrap(), vec!["กข"]);
+        // vowels, spaces and zero width characters are dropped
+        assert_eq!(front_text_candidates(" ก า\u{200B}ข ").unwrap(), vec!["กข"]);
+    }
+
+    #[test]
+    fn front_text_candidates_transliteration() {
+        assert_eq!(front_text_candidates("kh").unwrap(), vec!["ข", "ค", "ฆ"]);
+        assert_eq!(
+            front_text_candidates("KOB").unwrap(),
+            vec!["กบ", "ขบ", "คบ"]
+        );
+        assert_eq!(front_text_candidates("a").unwrap(), vec!["อ"]);
+        assert_eq!(front_text_candidates("ngม").unwrap(), vec!["งม"]);
+        assert_eq!(
+            front_text_candidates("q").unwrap_err().error,
+            "invalid_front_text"
+        );
+    }
+
+    #[test]
+    fn front_text_candidates_are_capped() {
+        // 6 readings of th to the 4th power is 1296 strings
+        let candidates = front_text_candidates("thththth").unwrap();
+        assert_eq!(candidates.len(), MAX_CANDIDATES);
+        assert_eq!(candidates[0], "ทททท");
+        assert!(candidates
+            .iter()
+            .all(|candidate| candidate.chars().count() == 4));
+    }
+
+    #[test]
+    fn shape_signature_by_first_appearance() {
+        assert_eq!(shape_signature(1), "a");
+        assert_eq!(shape_signature(1212), "abab");
+        assert_eq!(shape_signature(155), "abb");
+        assert_eq!(shape_signature(1000), "abbb");
+        assert_eq!(shape_signature(9876), "abcd");
+    }
+
+    #[test]
+    fn shape_matches_back_numbers() {
+        let matches = |shape: &str, number: i32| parse_shape(shape).unwrap().matches(number);
+        assert!(matches("xyy", 122));
+        assert!(!matches("xyy", 111));
+        assert!(matches("x55", 155));
+        assert!(matches("x55", 555));
+        assert!(matches("xyxy", 1212));
+        assert!(!matches("xyxy", 1111));
+        assert!(matches("1?1?", 1312));
+        assert!(!matches("1?1?", 131));
+        assert!(matches("ascending", 1234));
+        assert!(!matches("ascending", 12));
+        assert!(matches("descending", 987));
+        assert!(!matches("descending", 1234));
+        assert!(matches("palindrome", 1221));
+        assert!(!matches("palindrome", 7));
+        assert!(!matches("x", 0));
+        assert!(!matches("xxxx", 11111));
+    }
+
+    #[test]
+    fn shape_signatures_cover_back_numbers() {
+        let shape = parse_shape("xxyy").unwrap();
+        assert_eq!(shape.signatures(), vec!["aabb"]);
+        assert_eq!(shape.back_numbers().len(), 81);
+        let shape = parse_shape("x999").unwrap();
+        assert_eq!(shape.signatures(), vec!["aaaa", "abbb"]);
+        assert_eq!(parse_shape("????").unwrap().signatures().len(), 15);
+        assert_eq!(
+            parse_shape("ascending").unwrap().back_numbers(),
+            vec![123, 234, 345, 456, 567, 678, 789, 1234, 2345, 3456, 4567, 5678, 6789]
+        );
+    }
+
+    #[test]
+    fn front_text_candidates_empty_is_no_filter() {
+        assert!(front_text_candidates("").unwrap().is_empty());
+        assert!(front_text_candidates("  ").unwrap().is_empty());
+        assert_eq!(
+            front_text_candidates("า").unwrap_err().error,
+            "invalid_front_text"
+        );
+    }
+
+    #[test]
+    fn front_text_candidates_mixed_spelling() {
+        assert_eq!(front_text_candidates("kk").unwrap().len(), 9);
+        assert_eq!(front_text_candidates("kk").unwrap()[0], "กก");
+        assert_eq!(front_text_candidates("ก m").unwrap(), vec!["กม"]);
+        assert_eq!(
+            front_text_candidates("sa").unwrap(),
+            vec!["ส", "ศ", "ษ", "ซ"]
+        );
+    }
+}
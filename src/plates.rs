@@ -1,12 +1,16 @@
 use crate::{
     app_state::AppState,
+    authentication::AuthUser,
+    error::ApiError,
     pattern::analyze_pattern,
+    plate_number,
     query::{PlatesFilter, UsersFilter},
 };
-use axum::{extract::State, Json};
-use chrono::Utc;
+use axum::{extract::State, Extension, Json};
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Plates {
@@ -40,8 +44,41 @@ pub struct SpecialFront {
     pub front: String,
 }
 
+pub async fn validate_plates_owner(
+    pool: &Pool<Postgres>,
+    plates_id: i32,
+    users_id: i32,
+) -> Result<(), ApiError> {
+    let fetch: Result<Option<(i32,)>, sqlx::Error> =
+        sqlx::query_as("SELECT users_id FROM public.plates WHERE plates_id = $1")
+            .bind(plates_id)
+            .fetch_optional(pool)
+            .await;
+    match fetch {
+        Ok(ok) => match ok {
+            Some((owner,)) => {
+                if owner == users_id {
+                    Ok(())
+                } else {
+                    Err(ApiError::new(
+                        StatusCode::FORBIDDEN,
+                        "not_plates_owner",
+                        format!("plates_id {plates_id} does not belong to users_id {users_id}"),
+                    ))
+                }
+            }
+            None => Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                "plates_not_found",
+                format!("plates_id {plates_id} does not exist"),
+            )),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
 pub async fn fetch_special_front(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
 ) -> Result<Json<Vec<SpecialFront>>, StatusCode> {
     let fetch: Result<Vec<SpecialFront>, sqlx::Error> =
         sqlx::query_as("SELECT special_front_id, front FROM public.special_front")
@@ -57,178 +94,247 @@ pub async fn fetch_special_front(
 }
 
 pub async fn add_new_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<Plates>,
-) -> Result<Json<UniversalId>, StatusCode> {
-    let unique_text = format!(
-        "province_id({})-vehicle_type_id({})-front_number({})-front_text({})-back_number({})",
-        payload.province_id,
-        payload.vehicle_type_id,
+) -> Result<Json<UniversalId>, ApiError> {
+    plate_number::validate(
+        &payload.front_text,
         payload.front_number,
-        payload.front_text,
-        payload.back_number
-    );
-    let fetch: Result<Option<(i32,)>, sqlx::Error> =
-        sqlx::query_as("SELECT plates_id FROM public.plates WHERE (unique_text = $1)")
-            .bind(&unique_text)
-            .fetch_optional(&pool)
-            .await;
-    match fetch {
+        payload.back_number,
+        payload.vehicle_type_id,
+    )?;
+    let add_date = Utc::now();
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let insert: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("INSERT INTO public.plates(front_text, province_id, plates_type_id, users_id, total, add_date, front_number, back_number, special_front_id, vehicle_type_id, information) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT (province_id, vehicle_type_id, front_text, front_number, back_number) WHERE number_conflict IS FALSE DO NOTHING RETURNING plates_id")
+        .bind(&payload.front_text)
+        .bind(payload.province_id)
+        .bind(payload.plates_type_id)
+        .bind(users_id)
+        .bind(plate_number::digit_sum(
+            &payload.front_text,
+            payload.front_number,
+            payload.back_number,
+        ))
+        .bind(add_date)
+        .bind(payload.front_number)
+        .bind(payload.back_number)
+        .bind(payload.special_front_id)
+        .bind(payload.vehicle_type_id)
+        .bind(payload.information)
+        .fetch_optional(&mut *tx)
+        .await;
+    match insert {
         Ok(ok) => match ok {
-            Some(_) => Err(StatusCode::CONFLICT),
-            None => {
-                let add_date = Utc::now();
-                let insert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.plates(front_text, province_id, plates_type_id, users_id, total, add_date, unique_text, front_number, back_number, special_front_id, vehicle_type_id, information) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING plates_id")
-                    .bind(&payload.front_text)
-                    .bind(payload.province_id)
-                    .bind(payload.plates_type_id)
-                    .bind(payload.users_id)
-                    .bind(payload.total)
-                    .bind(add_date)
-                    .bind(&unique_text)
-                    .bind(payload.front_number)
-                    .bind(payload.back_number)
-                    .bind(payload.special_front_id)
-                    .bind(payload.vehicle_type_id)
-                    .bind(payload.information)
-                    .fetch_one(&pool)
-                    .await;
-                match insert {
-                    Ok((plates_id,)) => {
-                        let _ = if !payload.is_temporary {
-                            let insert_price = sqlx::query("INSERT INTO public.price_history(plates_id, price, add_date) VALUES ($1, $2, $3)")
-                            .bind(plates_id)
-                            .bind(payload.price)
-                            .bind(add_date)
-                            .execute(&pool)
-                            .await;
-                            match insert_price {
-                                Ok(_) => (),
-                                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-                            }
-                        };
-                        analyze_pattern(
-                            plates_id,
-                            &payload.front_text,
-                            payload.front_number,
-                            payload.back_number,
-                            add_date,
-                            payload.vehicle_type_id,
-                            &pool,
-                        )
-                        .await;
-                        Ok(Json(UniversalId { id: plates_id }))
-                    }
-                    Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            Some((plates_id,)) => {
+                // the plates is only kept together with its first price
+                if (!payload.is_temporary
+                    && insert_price(&mut tx, plates_id, payload.price, add_date)
+                        .await
+                        .is_err())
+                    || tx.commit().await.is_err()
+                {
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
                 }
+                analyze_pattern(
+                    plates_id,
+                    &payload.front_text,
+                    payload.front_number,
+                    payload.back_number,
+                    add_date,
+                    payload.vehicle_type_id,
+                    &pool,
+                )
+                .await;
+                Ok(Json(UniversalId { id: plates_id }))
             }
+            None => Err(StatusCode::CONFLICT.into()),
         },
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
+// keeps plates_stats in step with price_history, reaction counts are taken
+// from the reaction tables when plates is priced for the first time
+async fn update_price_stats(
+    tx: &mut Transaction<'static, Postgres>,
+    plates_id: i32,
+    price: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO public.plates_stats(plates_id, price, liked_plates_id_count, saved_plates_id_count) SELECT $1, $2, (SELECT COUNT(*) FROM public.liked_plates WHERE plates_id = $1), (SELECT COUNT(*) FROM public.saved_plates WHERE plates_id = $1) ON CONFLICT (plates_id) DO UPDATE SET previous_price = plates_stats.price, price = EXCLUDED.price")
+        .bind(plates_id)
+        .bind(price)
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+}
+
+// plates without a price have no plates_stats row yet, their reactions are
+// counted by update_price_stats
+async fn update_reacts_stats(
+    tx: &mut Transaction<'static, Postgres>,
+    plates_id: i32,
+    counter: &'static str,
+    delta: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "UPDATE public.plates_stats SET {counter} = {counter} + $1 WHERE plates_id = $2"
+    ))
+    .bind(delta)
+    .bind(plates_id)
+    .execute(&mut **tx)
+    .await
+    .map(|_| ())
+}
+
+async fn insert_price(
+    tx: &mut Transaction<'static, Postgres>,
+    plates_id: i32,
+    price: i32,
+    add_date: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO public.price_history(plates_id, price, add_date) VALUES ($1, $2, $3)")
+        .bind(plates_id)
+        .bind(price)
+        .bind(add_date)
+        .execute(&mut **tx)
+        .await?;
+    update_price_stats(tx, plates_id, price).await
+}
+
 pub async fn insert_new_price(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<Plates>,
-) -> StatusCode {
-    let add_date = Utc::now();
-    let insert: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("INSERT INTO public.price_history(plates_id, price, add_date) VALUES ($1, $2, $3) RETURNING plates_id")
-        .bind(payload.plates_id)
-        .bind(payload.price)
-        .bind(add_date)
-        .fetch_optional(&pool)
-        .await;
-    match insert {
-        Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
-            None => StatusCode::BAD_REQUEST,
-        },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+) -> Result<StatusCode, ApiError> {
+    validate_plates_owner(&pool, payload.plates_id, users_id).await?;
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let insert = insert_price(&mut tx, payload.plates_id, payload.price, Utc::now()).await;
+    if insert.is_err() || tx.commit().await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
     }
+    Ok(StatusCode::OK)
 }
 
 pub async fn edit_plates_information(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<Plates>,
-) -> StatusCode {
+) -> Result<StatusCode, ApiError> {
+    validate_plates_owner(&pool, payload.plates_id, users_id).await?;
     let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
-        "UPDATE public.plates SET information = $1 WHERE plates_id = $2 RETURNING plates_id",
+        "UPDATE public.plates SET information = $1 WHERE (plates_id = $2 AND users_id = $3) RETURNING plates_id",
     )
     .bind(payload.information)
     .bind(payload.plates_id)
+    .bind(users_id)
     .fetch_optional(&pool)
     .await;
     match update {
         Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
-            None => StatusCode::BAD_REQUEST,
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(StatusCode::BAD_REQUEST.into()),
         },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
 pub async fn edit_is_selling(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<Plates>,
-) -> StatusCode {
+) -> Result<StatusCode, ApiError> {
+    validate_plates_owner(&pool, payload.plates_id, users_id).await?;
     let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
-        "UPDATE public.plates SET is_selling = $1 WHERE plates_id = $2 RETURNING plates_id",
+        "UPDATE public.plates SET is_selling = $1 WHERE (plates_id = $2 AND users_id = $3) RETURNING plates_id",
     )
     .bind(payload.is_selling)
     .bind(payload.plates_id)
+    .bind(users_id)
     .fetch_optional(&pool)
     .await;
     match update {
         Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
-            None => StatusCode::BAD_REQUEST,
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(StatusCode::BAD_REQUEST.into()),
         },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
+// the total is recomputed from the stored plate number, the client value is ignored
 pub async fn edit_total(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<Plates>,
-) -> StatusCode {
+) -> Result<StatusCode, ApiError> {
+    validate_plates_owner(&pool, payload.plates_id, users_id).await?;
+    let fetch: Result<Option<(String, i32, i32)>, sqlx::Error> = sqlx::query_as(
+        "SELECT front_text, front_number, back_number FROM public.plates WHERE plates_id = $1",
+    )
+    .bind(payload.plates_id)
+    .fetch_optional(&pool)
+    .await;
+    let total = match fetch {
+        Ok(ok) => match ok {
+            Some((front_text, front_number, back_number)) => {
+                plate_number::digit_sum(&front_text, front_number, back_number)
+            }
+            None => return Err(StatusCode::BAD_REQUEST.into()),
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
     let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
-        "UPDATE public.plates SET total = $1 WHERE plates_id = $2 RETURNING plates_id",
+        "UPDATE public.plates SET total = $1 WHERE (plates_id = $2 AND users_id = $3) RETURNING plates_id",
     )
-    .bind(payload.total)
+    .bind(total)
     .bind(payload.plates_id)
+    .bind(users_id)
     .fetch_optional(&pool)
     .await;
     match update {
         Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
-            None => StatusCode::BAD_REQUEST,
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(StatusCode::BAD_REQUEST.into()),
         },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
 pub async fn delete_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(UniversalId { id }): Json<UniversalId>,
-) -> StatusCode {
-    let delete: Result<Option<(i32,)>, sqlx::Error> =
-        sqlx::query_as("DELETE FROM public.plates WHERE plates_id = $1 RETURNING plates_id")
-            .bind(id)
-            .fetch_optional(&pool)
-            .await;
+) -> Result<StatusCode, ApiError> {
+    validate_plates_owner(&pool, id, users_id).await?;
+    let delete: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
+        "DELETE FROM public.plates WHERE (plates_id = $1 AND users_id = $2) RETURNING plates_id",
+    )
+    .bind(id)
+    .bind(users_id)
+    .fetch_optional(&pool)
+    .await;
     match delete {
         Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
-            None => StatusCode::BAD_REQUEST,
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(StatusCode::BAD_REQUEST.into()),
         },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
 pub async fn edit_is_pin(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<Plates>,
-) -> StatusCode {
+) -> Result<StatusCode, ApiError> {
+    validate_plates_owner(&pool, payload.plates_id, users_id).await?;
     if payload.is_pin {
         let count: Result<(i64,), sqlx::Error> = sqlx::query_as(
             "SELECT COUNT(plates.plates_id) FROM public.plates WHERE plates.is_pin IS TRUE",
@@ -239,149 +345,187 @@ pub async fn edit_is_pin(
             Ok((ok,)) => {
                 if ok < 30 {
                     let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
-                        "UPDATE public.plates SET is_pin = $1 WHERE plates_id = $2 RETURNING plates_id",
+                        "UPDATE public.plates SET is_pin = $1 WHERE (plates_id = $2 AND users_id = $3) RETURNING plates_id",
                     )
                     .bind(payload.is_pin)
                     .bind(payload.plates_id)
+                    .bind(users_id)
                     .fetch_optional(&pool)
                     .await;
                     match update {
                         Ok(ok) => match ok {
-                            Some(_) => StatusCode::OK,
-                            None => StatusCode::BAD_REQUEST,
+                            Some(_) => Ok(StatusCode::OK),
+                            None => Err(StatusCode::BAD_REQUEST.into()),
                         },
-                        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
                     }
                 } else {
-                    StatusCode::CONFLICT
+                    Err(StatusCode::CONFLICT.into())
                 }
             }
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
         }
     } else {
         let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
-            "UPDATE public.plates SET is_pin = $1 WHERE plates_id = $2 RETURNING plates_id",
+            "UPDATE public.plates SET is_pin = $1 WHERE (plates_id = $2 AND users_id = $3) RETURNING plates_id",
         )
         .bind(payload.is_pin)
         .bind(payload.plates_id)
+        .bind(users_id)
         .fetch_optional(&pool)
         .await;
         match update {
             Ok(ok) => match ok {
-                Some(_) => StatusCode::OK,
-                None => StatusCode::BAD_REQUEST,
+                Some(_) => Ok(StatusCode::OK),
+                None => Err(StatusCode::BAD_REQUEST.into()),
             },
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    }
-}
-
-pub async fn analyze_new_pattern(
-    State(AppState { pool, client: _ }): State<AppState>,
-) -> StatusCode {
-    let fetch: Result<Vec<(i32, String, i32, i32, i32)>, sqlx::Error> =
-        sqlx::query_as("SELECT plates_id, front_text, front_number, back_number, vehicle_type_id FROM public.plates").fetch_all(&pool).await;
-    match fetch {
-        Ok(ok) => {
-            let list = ok.iter();
-            let add_date = Utc::now();
-            for (plates_id, front_text, front_number, back_number, vehicle_type_id) in list {
-                analyze_pattern(
-                    *plates_id,
-                    front_text,
-                    *front_number,
-                    *back_number,
-                    add_date,
-                    *vehicle_type_id,
-                    &pool,
-                )
-                .await;
-            }
-            StatusCode::OK
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
         }
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
 pub async fn add_liked_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
 ) -> StatusCode {
     let add_date = Utc::now();
-    let insert = sqlx::query(
-        "INSERT INTO public.liked_plates(users_id, plates_id, add_date) VALUES ($1, $2, $3)",
-    )
-    .bind(payload.users_id)
-    .bind(payload.plates_id)
-    .bind(add_date)
-    .execute(&pool)
-    .await;
-    match insert {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let insert = sqlx::query("INSERT INTO public.liked_plates(users_id, plates_id, add_date) VALUES ($1, $2, $3) ON CONFLICT (users_id, plates_id) DO NOTHING")
+        .bind(users_id)
+        .bind(payload.plates_id)
+        .bind(add_date)
+        .execute(&mut *tx)
+        .await;
+    // a repeated request inserts nothing and leaves the counter alone
+    let added = match insert {
+        Ok(ok) => ok.rows_affected() == 1,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if (added
+        && update_reacts_stats(&mut tx, payload.plates_id, "liked_plates_id_count", 1)
+            .await
+            .is_err())
+        || tx.commit().await.is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR;
     }
+    StatusCode::OK
 }
 
 pub async fn remove_liked_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
 ) -> StatusCode {
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
     let delete =
         sqlx::query("DELETE FROM public.liked_plates WHERE (users_id = $1 AND plates_id = $2)")
-            .bind(payload.users_id)
+            .bind(users_id)
             .bind(payload.plates_id)
-            .execute(&pool)
+            .execute(&mut *tx)
             .await;
-    match delete {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    let removed = match delete {
+        Ok(ok) => ok.rows_affected() as i64,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if update_reacts_stats(
+        &mut tx,
+        payload.plates_id,
+        "liked_plates_id_count",
+        -removed,
+    )
+    .await
+    .is_err()
+        || tx.commit().await.is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR;
     }
+    StatusCode::OK
 }
 
 pub async fn add_saved_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
 ) -> StatusCode {
     let add_date = Utc::now();
-    let insert = sqlx::query(
-        "INSERT INTO public.saved_plates(users_id, plates_id, add_date) VALUES ($1, $2, $3)",
-    )
-    .bind(payload.users_id)
-    .bind(payload.plates_id)
-    .bind(add_date)
-    .execute(&pool)
-    .await;
-    match insert {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let insert = sqlx::query("INSERT INTO public.saved_plates(users_id, plates_id, add_date) VALUES ($1, $2, $3) ON CONFLICT (users_id, plates_id) DO NOTHING")
+        .bind(users_id)
+        .bind(payload.plates_id)
+        .bind(add_date)
+        .execute(&mut *tx)
+        .await;
+    // a repeated request inserts nothing and leaves the counter alone
+    let added = match insert {
+        Ok(ok) => ok.rows_affected() == 1,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if (added
+        && update_reacts_stats(&mut tx, payload.plates_id, "saved_plates_id_count", 1)
+            .await
+            .is_err())
+        || tx.commit().await.is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR;
     }
+    StatusCode::OK
 }
 
 pub async fn remove_saved_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
 ) -> StatusCode {
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
     let delete =
         sqlx::query("DELETE FROM public.saved_plates WHERE (users_id = $1 AND plates_id = $2)")
-            .bind(payload.users_id)
+            .bind(users_id)
             .bind(payload.plates_id)
-            .execute(&pool)
+            .execute(&mut *tx)
             .await;
-    match delete {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    let removed = match delete {
+        Ok(ok) => ok.rows_affected() as i64,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if update_reacts_stats(
+        &mut tx,
+        payload.plates_id,
+        "saved_plates_id_count",
+        -removed,
+    )
+    .await
+    .is_err()
+        || tx.commit().await.is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR;
     }
+    StatusCode::OK
 }
 
 pub async fn add_liked_store(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<UsersFilter>,
 ) -> StatusCode {
     let add_date = Utc::now();
     let insert = sqlx::query(
         "INSERT INTO public.liked_store(users_id, store_id, add_date) VALUES ($1, $2, $3)",
     )
-    .bind(payload.users_id)
+    .bind(users_id)
     .bind(payload.store_id)
     .bind(add_date)
     .execute(&pool)
@@ -393,12 +537,13 @@ pub async fn add_liked_store(
 }
 
 pub async fn remove_liked_store(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<UsersFilter>,
 ) -> StatusCode {
     let delete =
         sqlx::query("DELETE FROM public.liked_store WHERE (users_id = $1 AND store_id = $2)")
-            .bind(payload.users_id)
+            .bind(users_id)
             .bind(payload.store_id)
             .execute(&pool)
             .await;
@@ -409,14 +554,15 @@ pub async fn remove_liked_store(
 }
 
 pub async fn add_saved_store(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<UsersFilter>,
 ) -> StatusCode {
     let add_date = Utc::now();
     let insert = sqlx::query(
         "INSERT INTO public.saved_store(users_id, store_id, add_date) VALUES ($1, $2, $3)",
     )
-    .bind(payload.users_id)
+    .bind(users_id)
     .bind(payload.store_id)
     .bind(add_date)
     .execute(&pool)
@@ -428,12 +574,13 @@ pub async fn add_saved_store(
 }
 
 pub async fn remove_saved_store(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<UsersFilter>,
 ) -> StatusCode {
     let delete =
         sqlx::query("DELETE FROM public.saved_store WHERE (users_id = $1 AND store_id = $2)")
-            .bind(payload.users_id)
+            .bind(users_id)
             .bind(payload.store_id)
             .execute(&pool)
             .await;
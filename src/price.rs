@@ -0,0 +1,235 @@
+use crate::{
+    app_state::AppState,
+    authentication::AuthUser,
+    error::ApiError,
+    pattern::{fetch_active_patterns, matched_patterns},
+    plate_number::number_sum,
+};
+use axum::{extract::State, Extension, Json};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PricePoint {
+    pub price_history_id: i32,
+    pub price: i32,
+    pub add_date: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PriceTrend {
+    pub plates_id: i32,
+    pub history: Vec<PricePoint>,
+    pub listing_price: i32,
+    pub latest_price: i32,
+    pub lowest_price: i32,
+    pub highest_price: i32,
+    pub percent_change: f64,
+    pub price_dropped: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PriceFilter {
+    pub plates_id: i32,
+}
+
+impl PriceTrend {
+    // history must be ordered from oldest to latest and not be empty
+    fn from_history(plates_id: i32, history: Vec<PricePoint>) -> Self {
+        let listing_price = history[0].price;
+        let latest_price = history[history.len() - 1].price;
+        let previous_price = history.len().checked_sub(2).map(|i| history[i].price);
+        let percent_change = if listing_price == 0 {
+            0.0
+        } else {
+            f64::from(latest_price - listing_price) / f64::from(listing_price) * 100.0
+        };
+        PriceTrend {
+            plates_id,
+            listing_price,
+            latest_price,
+            lowest_price: history.iter().map(|point| point.price).min().unwrap_or(0),
+            highest_price: history.iter().map(|point| point.price).max().unwrap_or(0),
+            percent_change,
+            price_dropped: previous_price.is_some_and(|previous| latest_price < previous),
+            history,
+        }
+    }
+}
+
+// listings that are not for sale are only visible to their owner
+pub async fn query_price_history(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<PriceFilter>,
+) -> Result<Json<PriceTrend>, ApiError> {
+    let fetch: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("SELECT plates_id FROM public.plates WHERE plates_id = $1 AND ((is_selling IS TRUE AND is_temporary IS NOT TRUE) OR users_id = $2)")
+        .bind(payload.plates_id)
+        .bind(users_id)
+        .fetch_optional(&pool)
+        .await;
+    match fetch {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                "plates_not_found",
+                format!("plates {} does not exist", payload.plates_id),
+            ))
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+    let fetch: Result<Vec<PricePoint>, sqlx::Error> = sqlx::query_as("SELECT price_history_id, price, add_date::TEXT FROM public.price_history WHERE plates_id = $1 ORDER BY price_history_id ASC")
+        .bind(payload.plates_id)
+        .fetch_all(&pool)
+        .await;
+    match fetch {
+        Ok(ok) => {
+            if ok.is_empty() {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "price_not_found",
+                    format!("plates {} has no price", payload.plates_id),
+                ));
+            }
+            Ok(Json(PriceTrend::from_history(payload.plates_id, ok)))
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+const COMPARABLE_LIMIT: i32 = 20;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EstimateInput {
+    pub front_text: String,
+    pub front_number: i32,
+    pub back_number: i32,
+    pub province_id: i32,
+    pub vehicle_type_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Comparable {
+    pub plates_id: i32,
+    pub front_text: String,
+    pub front_number: i32,
+    pub back_number: i32,
+    pub province_id: i32,
+    pub price: i32,
+    pub same_back_number: bool,
+    pub same_pattern: bool,
+    pub same_province: bool,
+    pub similar_digit_sum: bool,
+    pub score: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Estimate {
+    pub low_price: Option<i32>,
+    pub suggested_price: Option<i32>,
+    pub high_price: Option<i32>,
+    pub pattern_list: Vec<String>,
+    pub comparables: Vec<Comparable>,
+}
+
+// nearest rank percentile of sorted prices
+fn percentile(sorted: &[i32], percent: usize) -> Option<i32> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+// comparables are for sale plates of the same vehicle type sharing the back
+// number, a pattern or a digit sum within 1, ranked by how much they share
+pub async fn estimate_price(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<EstimateInput>,
+) -> Result<Json<Estimate>, ApiError> {
+    let patterns = match fetch_active_patterns(&pool).await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let pattern_id_list = matched_patterns(
+        &patterns,
+        &payload.front_text,
+        payload.front_number,
+        payload.back_number,
+        payload.vehicle_type_id,
+    );
+    let pattern_list = patterns
+        .iter()
+        .filter(|pattern| pattern_id_list.contains(&pattern.pattern_id))
+        .map(|pattern| pattern.name.clone())
+        .collect();
+    let fetch: Result<Vec<Comparable>, sqlx::Error> = sqlx::query_as(
+        "WITH comparable AS (
+    SELECT plates.plates_id,
+        plates.front_text,
+        plates.front_number,
+        plates.back_number,
+        plates.province_id,
+        latest_price.price,
+        plates.back_number = $1 AS same_back_number,
+        EXISTS (
+            SELECT 1
+            FROM public.plates_pattern
+            WHERE plates_pattern.plates_id = plates.plates_id
+                AND plates_pattern.pattern_id = ANY($2)
+        ) AS same_pattern,
+        plates.province_id = $3 AS same_province,
+        ABS(
+            (
+                SELECT SUM(digit::INTEGER)
+                FROM REGEXP_SPLIT_TO_TABLE(plates.back_number::TEXT, '') AS digit
+            ) - $4
+        ) <= 1 AS similar_digit_sum
+    FROM public.plates_stats AS latest_price
+        INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
+    WHERE plates.vehicle_type_id = $5
+        AND plates.is_selling IS TRUE
+        AND plates.is_temporary IS NOT TRUE
+        AND NOT (
+            plates.front_text = $6
+            AND plates.front_number = $7
+            AND plates.back_number = $1
+            AND plates.province_id = $3
+        )
+)
+SELECT comparable.*,
+    4 * same_back_number::INTEGER + 3 * same_pattern::INTEGER + same_province::INTEGER + similar_digit_sum::INTEGER AS score
+FROM comparable
+WHERE same_back_number
+    OR same_pattern
+    OR similar_digit_sum
+ORDER BY score DESC,
+    plates_id DESC
+LIMIT $8",
+    )
+    .bind(payload.back_number)
+    .bind(&pattern_id_list)
+    .bind(payload.province_id)
+    .bind(number_sum(payload.back_number))
+    .bind(payload.vehicle_type_id)
+    .bind(&payload.front_text)
+    .bind(payload.front_number)
+    .bind(COMPARABLE_LIMIT)
+    .fetch_all(&pool)
+    .await;
+    match fetch {
+        Ok(ok) => {
+            let mut prices: Vec<i32> = ok.iter().map(|comparable| comparable.price).collect();
+            prices.sort_unstable();
+            Ok(Json(Estimate {
+                low_price: percentile(&prices, 25),
+                suggested_price: percentile(&prices, 50),
+                high_price: percentile(&prices, 75),
+                pattern_list,
+                comparables: ok,
+            }))
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
@@ -1,8 +1,8 @@
-use crate::{app_state::AppState, authentication::Authentication};
+use crate::{app_state::AppState, authentication::AuthUser};
 use axum::{
     extract::{Query, State},
     http::StatusCode,
-    Json,
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -17,8 +17,8 @@ pub struct Profile {
 }
 
 pub async fn fetch_profile(
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<Authentication>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
 ) -> Result<Json<Profile>, StatusCode> {
     let fetch: Result<
         (
@@ -30,7 +30,7 @@ pub async fn fetch_profile(
         ),
         sqlx::Error,
     > = sqlx::query_as("SELECT name, email, profile_uri, cover_uri, information FROM public.users WHERE users_id = $1")
-        .bind(payload.users_id)
+        .bind(users_id)
         .fetch_one(&pool)
         .await;
     match fetch {
@@ -47,14 +47,14 @@ pub async fn fetch_profile(
 
 pub async fn edit_name(
     Query(params): Query<HashMap<String, String>>,
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<Authentication>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
 ) -> StatusCode {
     match params.get("name") {
         Some(name) => {
             let update = sqlx::query("UPDATE public.users SET name = $1 WHERE users_id = $2")
                 .bind(name)
-                .bind(payload.users_id)
+                .bind(users_id)
                 .execute(&pool)
                 .await;
             match update {
@@ -68,15 +68,15 @@ pub async fn edit_name(
 
 pub async fn edit_information(
     Query(params): Query<HashMap<String, String>>,
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<Authentication>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
 ) -> StatusCode {
     match params.get("information") {
         Some(information) => {
             let update =
                 sqlx::query("UPDATE public.users SET information = $1 WHERE users_id = $2")
                     .bind(information)
-                    .bind(payload.users_id)
+                    .bind(users_id)
                     .execute(&pool)
                     .await;
             match update {
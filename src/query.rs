@@ -1,7 +1,14 @@
-use crate::app_state::AppState;
-use axum::{extract::State, Json};
+use crate::{
+    app_state::AppState,
+    authentication::AuthUser,
+    error::ApiError,
+    plate_number::{digit_sum, front_text_candidates, luck, lucky_score, Luck},
+    query_builder::{escape_like, invalid_cursor, Cursor, PlatesQuery, Predicate, MAX_PAGE},
+};
+use axum::{extract::State, Extension, Json};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlatesFilter {
@@ -21,14 +28,93 @@ pub struct PlatesFilter {
     pub sort_by: String,
     pub plates_type_id_list: Vec<i32>,
     pub province_id_list: Vec<i32>,
+    pub seed: Option<String>,
+    pub cursor: Option<String>,
     pub limit: i32,
     pub offset: i32,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlatesSearch {
+    pub front_text: Option<String>,
+    pub front_text_fuzzy: Option<String>,
+    pub front_number: Option<i32>,
+    pub back_number: Option<i32>,
+    pub back_number_contains: Option<i32>,
+    pub back_number_shape: Option<String>,
+    pub pattern_list: Option<Vec<String>>,
+    pub hashtag: Option<String>,
+    pub luck: Option<Luck>,
+    pub total: Option<i32>,
+    pub total_list: Option<Vec<i32>>,
+    pub total_min: Option<i32>,
+    pub total_max: Option<i32>,
+    pub special_front: bool,
+    pub price_min: Option<i32>,
+    pub price_max: Option<i32>,
+    pub plates_type_id_list: Option<Vec<i32>>,
+    pub province_id_list: Option<Vec<i32>>,
+    pub vehicle_type_id_list: Option<Vec<i32>>,
+    pub sort_by: String,
+    pub seed: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: i32,
+    pub offset: i32,
+}
+
+impl Default for PlatesSearch {
+    fn default() -> Self {
+        PlatesSearch {
+            front_text: None,
+            front_text_fuzzy: None,
+            front_number: None,
+            back_number: None,
+            back_number_contains: None,
+            back_number_shape: None,
+            pattern_list: None,
+            hashtag: None,
+            luck: None,
+            total: None,
+            total_list: None,
+            total_min: None,
+            total_max: None,
+            special_front: false,
+            price_min: None,
+            price_max: None,
+            plates_type_id_list: None,
+            province_id_list: None,
+            vehicle_type_id_list: None,
+            sort_by: "addDate".to_string(),
+            seed: None,
+            cursor: None,
+            limit: 30,
+            offset: 0,
+        }
+    }
+}
+
+impl From<&PlatesFilter> for PlatesSearch {
+    fn from(payload: &PlatesFilter) -> Self {
+        PlatesSearch {
+            price_max: Some(payload.price_under),
+            plates_type_id_list: Some(payload.plates_type_id_list.clone()),
+            province_id_list: Some(payload.province_id_list.clone()),
+            sort_by: payload.sort_by.clone(),
+            seed: payload.seed.clone(),
+            cursor: payload.cursor.clone(),
+            limit: payload.limit,
+            offset: payload.offset,
+            ..PlatesSearch::default()
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlatesGroup {
     pub exact: Vec<PlatesData>,
     pub suggestion: Vec<PlatesData>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -57,6 +143,22 @@ pub struct PlatesData {
     pub saved_plates_id_count: i64,
     pub reacts_count: i64,
     pub rownumber: i64,
+    pub price_dropped: bool,
+    #[sqlx(skip)]
+    pub digit_sum: i32,
+    #[sqlx(skip)]
+    pub luck: Luck,
+    #[sqlx(skip)]
+    pub lucky_score: i32,
+}
+
+impl PlatesData {
+    pub fn with_plate_number(mut self) -> Self {
+        self.digit_sum = digit_sum(&self.front_text, self.front_number, self.back_number);
+        self.luck = luck(self.digit_sum);
+        self.lucky_score = lucky_score(&self.front_text, self.front_number, self.back_number);
+        self
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -64,6 +166,7 @@ pub struct UsersFilter {
     pub users_id: i32,
     pub store_id: i32,
     pub search_text: String,
+    pub cursor: Option<String>,
     pub limit: i32,
     pub offset: i32,
 }
@@ -71,6 +174,7 @@ pub struct UsersFilter {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UsersGroup {
     pub exact: Vec<UsersData>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -88,1469 +192,311 @@ pub struct UsersData {
     pub reacts_count: i64,
     pub total_assets: i64,
     pub plates_count: i64,
-    pub average_score: Option<i64>,
+    pub average_score: Option<f64>,
 }
 
-fn order_by(sort_by: String) -> &'static str {
-    match sort_by.as_str() {
-        "priceLowToHigh" => "latest_price.price ASC",
-        "priceHighToLow" => "latest_price.price DESC",
-        "reacts" => "latest_price.reacts_count DESC",
-        "random" => "RANDOM()",
-        "addDate" => "plates.add_date DESC",
-        _ => "plates.add_date DESC",
-    }
+fn province_filter(province_id_list: &[i32], province_id: i32) -> Vec<i32> {
+    province_id_list
+        .iter()
+        .copied()
+        .filter(|id| match province_id {
+            0 => *id != 1,
+            1 => *id == 1,
+            _ => *id != 0,
+        })
+        .collect()
 }
 
-fn province_text(province_id: i32) -> &'static str {
-    match province_id {
-        0 => "!= 1",
-        1 => "= 1",
-        _ => "!= 0",
+pub async fn fetch_plates_group(
+    pool: &Pool<Postgres>,
+    users_id: i32,
+    search: &PlatesSearch,
+) -> Result<PlatesGroup, ApiError> {
+    let query = PlatesQuery::from_search(users_id, search)?;
+    // empty front text means no front text filter
+    let candidates = match &search.front_text_fuzzy {
+        Some(front_text) => front_text_candidates(front_text)?,
+        None => Vec::new(),
+    };
+    if !candidates.is_empty() {
+        let query = query.rank_by(candidates.clone());
+        let exact_query = query
+            .clone()
+            .and(Predicate::FrontTextPrefixIn(candidates.clone()));
+        let similar_query = query
+            .clone()
+            .and(Predicate::FrontTextSimilar(candidates.clone()));
+        let (exact_query, similar_query) = match search.back_number {
+            Some(back_number) => (
+                exact_query.and(Predicate::BackNumber(back_number)),
+                similar_query.and(Predicate::BackNumber(back_number)),
+            ),
+            None => (exact_query, similar_query),
+        };
+        let exact = match exact_query.fetch_all(pool).await {
+            Ok(ok) => ok,
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+        };
+        // plates with the typed front text whose back number contains the
+        // typed one come first, then near misses of the front text
+        let mut suggestion = match search.back_number {
+            Some(back_number) => match query
+                .and(Predicate::FrontTextPrefixIn(candidates))
+                .and(Predicate::BackNumberContains(back_number))
+                .fetch_all(pool)
+                .await
+            {
+                Ok(ok) => ok,
+                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+            },
+            None => Vec::new(),
+        };
+        match similar_query.fetch_all(pool).await {
+            Ok(ok) => suggestion.extend(ok),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+        }
+        suggestion.retain(|plates| !exact.iter().any(|e| e.plates_id == plates.plates_id));
+        suggestion.truncate(search.limit.clamp(1, MAX_PAGE) as usize);
+        return Ok(PlatesGroup {
+            exact,
+            suggestion,
+            next_cursor: None,
+        });
+    }
+    // the cursor pages exact, suggestions are only served with the first page
+    match search.back_number {
+        Some(back_number) => {
+            let exact_query = query.clone().and(Predicate::BackNumber(back_number));
+            let exact = match exact_query.fetch_all(pool).await {
+                Ok(ok) => ok,
+                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+            };
+            let suggestion = match search.cursor {
+                Some(_) => Vec::new(),
+                None => match query
+                    .and(Predicate::BackNumberContains(back_number))
+                    .fetch_all(pool)
+                    .await
+                {
+                    Ok(ok) => ok,
+                    Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+                },
+            };
+            Ok(PlatesGroup {
+                next_cursor: exact_query.next_cursor(&exact),
+                exact,
+                suggestion,
+            })
+        }
+        None => match query.fetch_all(pool).await {
+            Ok(ok) => Ok(PlatesGroup {
+                next_cursor: query.next_cursor(&ok),
+                exact: ok,
+                suggestion: Vec::new(),
+            }),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+        },
     }
 }
 
+pub async fn search_plates(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<PlatesSearch>,
+) -> Result<Json<PlatesGroup>, ApiError> {
+    fetch_plates_group(&pool, users_id, &payload)
+        .await
+        .map(Json)
+}
+
 pub async fn query_special_front(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.special_front_id != 1
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        special_front: true,
+        ..PlatesSearch::from(&payload)
+    };
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn query_pattern(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let pattern = payload.pattern;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.{pattern} ON {pattern}.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        pattern_list: Some(vec![payload.pattern.clone()]),
+        ..PlatesSearch::from(&payload)
+    };
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn query_plates_type_province(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let province = province_text(payload.province_id);
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.plates_type_id = $7
-    AND plates.province_id {province}
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(payload.plates_type_id)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        plates_type_id_list: Some(
+            payload
+                .plates_type_id_list
+                .iter()
+                .copied()
+                .filter(|id| *id == payload.plates_type_id)
+                .collect(),
+        ),
+        province_id_list: Some(province_filter(
+            &payload.province_id_list,
+            payload.province_id,
+        )),
+        ..PlatesSearch::from(&payload)
+    };
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn query_vehicle_type_province(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let province = province_text(payload.province_id);
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.vehicle_type_id = $7
-    AND plates.province_id {province}
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(payload.vehicle_type_id)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        vehicle_type_id_list: Some(vec![payload.vehicle_type_id]),
+        province_id_list: Some(province_filter(
+            &payload.province_id_list,
+            payload.province_id,
+        )),
+        ..PlatesSearch::from(&payload)
+    };
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn query_suggestion_back_number(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let back_number = payload.back_number;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.back_number = $7
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(back_number)
-        .fetch_all(&pool)
-        .await;
-    let exact = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND CAST(plates.back_number AS text) LIKE '%{back_number}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    let suggestion = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        back_number: Some(payload.back_number),
+        ..PlatesSearch::from(&payload)
     };
-    Ok(Json(PlatesGroup { exact, suggestion }))
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn query_explore(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch::from(&payload);
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn search_number_text_number(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let search_text_front_text = payload.search_text_front_text;
-    let search_text_back_number = payload.search_text_back_number;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.front_number = $7
-    AND plates.back_number = $8
-    AND plates.front_text LIKE '{search_text_front_text}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(payload.search_text_front_number)
-        .bind(payload.search_text_back_number)
-        .fetch_all(&pool)
-        .await;
-    let exact = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        front_text_fuzzy: Some(payload.search_text_front_text.clone()),
+        front_number: Some(payload.search_text_front_number),
+        back_number: Some(payload.search_text_back_number),
+        ..PlatesSearch::from(&payload)
     };
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.front_number = $7
-    AND CAST(plates.back_number AS text) LIKE '%{search_text_back_number}%'
-    AND plates.front_text LIKE '{search_text_front_text}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(payload.search_text_front_number)
-        .fetch_all(&pool)
-        .await;
-    let suggestion = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-    Ok(Json(PlatesGroup { exact, suggestion }))
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn search_number_text(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let search_text_front_text = payload.search_text_front_text;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.front_number = $7
-    AND plates.front_text LIKE '{search_text_front_text}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(payload.search_text_front_number)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        front_text_fuzzy: Some(payload.search_text_front_text.clone()),
+        front_number: Some(payload.search_text_front_number),
+        ..PlatesSearch::from(&payload)
+    };
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn search_text_number(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let search_text_front_text = payload.search_text_front_text;
-    let search_text_back_number = payload.search_text_back_number;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.back_number = $7
-    AND plates.front_text LIKE '{search_text_front_text}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(search_text_back_number)
-        .fetch_all(&pool)
-        .await;
-    let exact = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND CAST(plates.back_number AS text) LIKE '%{search_text_back_number}%'
-    AND plates.front_text LIKE '{search_text_front_text}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    let suggestion = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        front_text_fuzzy: Some(payload.search_text_front_text.clone()),
+        back_number: Some(payload.search_text_back_number),
+        ..PlatesSearch::from(&payload)
     };
-    Ok(Json(PlatesGroup { exact, suggestion }))
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn search_text(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let search_text_front_text = payload.search_text_front_text;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.front_text LIKE '{search_text_front_text}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        front_text_fuzzy: Some(payload.search_text_front_text.clone()),
+        ..PlatesSearch::from(&payload)
+    };
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn search_number(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sort_by = order_by(payload.sort_by);
-    let search_text_back_number = payload.search_text_back_number;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND plates.back_number = $7
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .bind(search_text_back_number)
-        .fetch_all(&pool)
-        .await;
-    let exact = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+) -> Result<Json<PlatesGroup>, ApiError> {
+    let search = PlatesSearch {
+        back_number: Some(payload.search_text_back_number),
+        ..PlatesSearch::from(&payload)
     };
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND latest_price.price <= $2
-    AND plates.plates_type_id IN (
-        SELECT unnest ($3::integer [])
-    )
-    AND plates.province_id IN (
-        SELECT unnest ($4::integer [])
-    )
-    AND CAST(plates.back_number AS text) LIKE '%{search_text_back_number}%'
-ORDER BY {sort_by}
-LIMIT $5 OFFSET $6"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.price_under)
-        .bind(&payload.plates_type_id_list)
-        .bind(&payload.province_id_list)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    let suggestion = match fetch {
-        Ok(ok) => ok,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-    Ok(Json(PlatesGroup { exact, suggestion }))
+    fetch_plates_group(&pool, users_id, &search).await.map(Json)
 }
 
 pub async fn query_plates_info(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<PlatesFilter>,
 ) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    WHERE price_history.plates_id = $2
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.plates_id)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
+    let query =
+        PlatesQuery::new(users_id, "addDate", 1, 0).and(Predicate::Plates(payload.plates_id));
+    match query.fetch_all(&pool).await {
         Ok(ok) => Ok(Json(PlatesGroup {
             exact: ok,
             suggestion: Vec::new(),
+            next_cursor: None,
         })),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
 pub async fn search_users_info(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<UsersFilter>,
-) -> Result<Json<UsersGroup>, StatusCode> {
-    let search_text = payload.search_text;
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber,
+) -> Result<Json<UsersGroup>, ApiError> {
+    let search_text = format!("%{}%", escape_like(&payload.search_text));
+    let limit = payload.limit.clamp(1, MAX_PAGE);
+    // stores are listed by users_id so the cursor is the last users_id served
+    let after = match &payload.cursor {
+        Some(cursor) => match Cursor::decode(cursor)? {
+            Cursor { sort, id, .. } if sort == "users" => id,
+            _ => return Err(invalid_cursor()),
+        },
+        None => 0,
+    };
+    let sql = "WITH latest_price AS (
+    SELECT plates_stats.plates_id,
+        plates_stats.price,
         plates.users_id
-    FROM public.price_history
-        INNER JOIN public.plates ON plates.plates_id = price_history.plates_id
+    FROM public.plates_stats
+        INNER JOIN public.plates ON plates.plates_id = plates_stats.plates_id
         INNER JOIN public.users ON users.users_id = plates.users_id
-        AND users.name LIKE '%{search_text}%'
+        AND users.name LIKE $2
         AND plates.is_selling IS TRUE
         AND plates.is_temporary IS NOT TRUE
 )
@@ -1567,47 +513,67 @@ SELECT users.users_id,
     COUNT(ls.liked_store_id) + COUNT(ss.saved_store_id) AS reacts_count,
     SUM(latest_price.price) AS total_assets,
     COUNT(latest_price.plates_id) AS plates_count,
-    AVG(rating.score) AS average_score
+    (
+        SELECT AVG(rating.score)::FLOAT8
+        FROM public.rating
+        WHERE rating.store_id = users.users_id
+    ) AS average_score
 FROM latest_price
     INNER JOIN public.users ON users.users_id = latest_price.users_id
     LEFT JOIN public.liked_store ON liked_store.store_id = latest_price.users_id
     AND liked_store.users_id = $1
     LEFT JOIN public.saved_store ON saved_store.store_id = latest_price.users_id
     AND saved_store.users_id = $1
-    LEFT JOIN public.rating ON rating.store_id = latest_price.users_id
     LEFT JOIN public.liked_store AS ls ON ls.store_id = latest_price.users_id
     LEFT JOIN public.saved_store AS ss ON ss.store_id = latest_price.users_id
-WHERE latest_price.rownumber = 1
+WHERE users.users_id > $3
 GROUP BY users.users_id,
     liked_store.liked_store_id,
-    saved_store.saved_store_id"
-    );
-    let fetch: Result<Vec<UsersData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
+    saved_store.saved_store_id
+ORDER BY users.users_id ASC
+LIMIT $4";
+    let fetch: Result<Vec<UsersData>, sqlx::Error> = sqlx::query_as(sql)
+        .bind(users_id)
+        .bind(&search_text)
+        .bind(after)
+        .bind(limit)
         .fetch_all(&pool)
         .await;
     match fetch {
-        Ok(ok) => Ok(Json(UsersGroup { exact: ok })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Ok(ok) => {
+            let next_cursor = match ok.last() {
+                Some(users) if ok.len() >= limit as usize => Some(
+                    Cursor {
+                        sort: "users".to_string(),
+                        key: String::new(),
+                        id: users.users_id,
+                        seed: String::new(),
+                    }
+                    .encode(),
+                ),
+                _ => None,
+            };
+            Ok(Json(UsersGroup {
+                exact: ok,
+                next_cursor,
+            }))
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
 pub async fn query_users_info(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<UsersFilter>,
 ) -> Result<Json<UsersGroup>, StatusCode> {
     let sql = format!(
         "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber,
+    SELECT plates_stats.plates_id,
+        plates_stats.price,
         plates.users_id
-    FROM public.price_history
-        INNER JOIN public.plates ON plates.plates_id = price_history.plates_id
+    FROM public.plates_stats
+        INNER JOIN public.plates ON plates.plates_id = plates_stats.plates_id
         AND plates.users_id = $2
         AND plates.is_selling IS TRUE
         AND plates.is_temporary IS NOT TRUE
@@ -1625,196 +591,75 @@ SELECT users.users_id,
     COUNT(ls.liked_store_id) + COUNT(ss.saved_store_id) AS reacts_count,
     SUM(latest_price.price) AS total_assets,
     COUNT(latest_price.plates_id) AS plates_count,
-    AVG(rating.score) AS average_score
+    (
+        SELECT AVG(rating.score)::FLOAT8
+        FROM public.rating
+        WHERE rating.store_id = users.users_id
+    ) AS average_score
 FROM latest_price
     INNER JOIN public.users ON users.users_id = latest_price.users_id
     LEFT JOIN public.liked_store ON liked_store.store_id = latest_price.users_id
     AND liked_store.users_id = $1
     LEFT JOIN public.saved_store ON saved_store.store_id = latest_price.users_id
     AND saved_store.users_id = $1
-    LEFT JOIN public.rating ON rating.store_id = latest_price.users_id
     LEFT JOIN public.liked_store AS ls ON ls.store_id = latest_price.users_id
     LEFT JOIN public.saved_store AS ss ON ss.store_id = latest_price.users_id
-WHERE latest_price.rownumber = 1
 GROUP BY users.users_id,
     liked_store.liked_store_id,
     saved_store.saved_store_id"
     );
     let fetch: Result<Vec<UsersData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
+        .bind(users_id)
         .bind(payload.store_id)
         .fetch_all(&pool)
         .await;
     match fetch {
-        Ok(ok) => Ok(Json(UsersGroup { exact: ok })),
+        Ok(ok) => Ok(Json(UsersGroup {
+            exact: ok,
+            next_cursor: None,
+        })),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
-pub async fn query_users_plates_pin(
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<UsersFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        INNER JOIN public.plates ON plates.plates_id = price_history.plates_id
-        AND plates.users_id = $2
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND plates.users_id = $2
-    AND plates.is_pin IS TRUE
-ORDER BY plates.add_date DESC
-LIMIT $3 OFFSET $4"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.store_id)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
+async fn fetch_store_plates(
+    pool: &Pool<Postgres>,
+    users_id: i32,
+    payload: &UsersFilter,
+    is_pin: bool,
+) -> Result<PlatesGroup, ApiError> {
+    let mut query = PlatesQuery::new(users_id, "addDate", payload.limit, payload.offset)
+        .and(Predicate::Store(payload.store_id))
+        .and(Predicate::Pinned(is_pin));
+    if let Some(cursor) = &payload.cursor {
+        query = query.after(Cursor::decode(cursor)?)?;
+    }
+    match query.fetch_all(pool).await {
+        Ok(ok) => Ok(PlatesGroup {
+            next_cursor: query.next_cursor(&ok),
             exact: ok,
             suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
+pub async fn query_users_plates_pin(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<UsersFilter>,
+) -> Result<Json<PlatesGroup>, ApiError> {
+    fetch_store_plates(&pool, users_id, &payload, true)
+        .await
+        .map(Json)
+}
+
 pub async fn query_users_plates_unpin(
-    State(AppState { pool, client: _ }): State<AppState>,
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
     Json(payload): Json<UsersFilter>,
-) -> Result<Json<PlatesGroup>, StatusCode> {
-    let sql = format!(
-        "WITH latest_price AS (
-    SELECT price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price,
-        COUNT(lp.liked_plates_id) AS liked_plates_id_count,
-        COUNT(sp.saved_plates_id) AS saved_plates_id_count,
-        COUNT(lp.liked_plates_id) + COUNT(sp.saved_plates_id) AS reacts_count,
-        ROW_NUMBER() OVER (
-            PARTITION BY price_history.plates_id
-            ORDER BY price_history.price_history_id DESC
-        ) AS rownumber
-    FROM public.price_history
-        INNER JOIN public.plates ON plates.plates_id = price_history.plates_id
-        AND plates.users_id = $2
-        LEFT JOIN public.liked_plates AS lp ON lp.plates_id = price_history.plates_id
-        LEFT JOIN public.saved_plates AS sp ON sp.plates_id = price_history.plates_id
-    GROUP BY price_history.price_history_id,
-        price_history.plates_id,
-        price_history.price
-)
-SELECT plates.plates_id,
-    plates.front_text,
-    plates.plates_type_id,
-    plates.plates_uri,
-    plates.total,
-    plates.add_date::TEXT,
-    plates.front_number,
-    plates.back_number,
-    plates.vehicle_type_id,
-    plates.users_id,
-    plates.special_front_id,
-    plates.province_id,
-    plates.information,
-    latest_price.price,
-    users.name,
-    users.profile_uri,
-    liked_plates.liked_plates_id,
-    saved_plates.saved_plates_id,
-    liked_store.liked_store_id,
-    saved_store.saved_store_id,
-    latest_price.liked_plates_id_count,
-    latest_price.saved_plates_id_count,
-    latest_price.reacts_count,
-    latest_price.rownumber
-FROM latest_price
-    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
-    INNER JOIN public.users ON users.users_id = plates.users_id
-    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id
-    AND liked_plates.users_id = $1
-    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id
-    AND saved_plates.users_id = $1
-    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id
-    AND liked_store.users_id = $1
-    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id
-    AND saved_store.users_id = $1
-WHERE latest_price.rownumber = 1
-    AND plates.is_selling IS TRUE
-    AND plates.is_temporary IS NOT TRUE
-    AND plates.users_id = $2
-    AND plates.is_pin IS NOT TRUE
-ORDER BY plates.add_date DESC
-LIMIT $3 OFFSET $4"
-    );
-    let fetch: Result<Vec<PlatesData>, sqlx::Error> = sqlx::query_as(&sql)
-        .bind(payload.users_id)
-        .bind(payload.store_id)
-        .bind(payload.limit)
-        .bind(payload.offset)
-        .fetch_all(&pool)
-        .await;
-    match fetch {
-        Ok(ok) => Ok(Json(PlatesGroup {
-            exact: ok,
-            suggestion: Vec::new(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<PlatesGroup>, ApiError> {
+    fetch_store_plates(&pool, users_id, &payload, false)
+        .await
+        .map(Json)
 }
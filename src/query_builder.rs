@@ -0,0 +1,648 @@
+use crate::{
+    error::ApiError,
+    hashtag::normalize_tag,
+    plate_number::{luck_totals, parse_shape, BackNumberShape},
+    query::{PlatesData, PlatesSearch},
+};
+use hyper::StatusCode;
+use sqlx::{Pool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+const SIMILARITY_THRESHOLD: f32 = 0.3;
+
+// most rows a client can ask for in one page of any list
+pub const MAX_PAGE: i32 = 100;
+
+// latest_price is the maintained plates_stats row, see plates_stats.sql
+const LATEST_PRICE: &str = "SELECT plates.plates_id,
+    plates.front_text,
+    plates.plates_type_id,
+    plates.plates_uri,
+    plates.total,
+    plates.add_date::TEXT,
+    plates.front_number,
+    plates.back_number,
+    plates.vehicle_type_id,
+    plates.users_id,
+    plates.special_front_id,
+    plates.province_id,
+    plates.information,
+    latest_price.price,
+    users.name,
+    users.profile_uri,
+    liked_plates.liked_plates_id,
+    saved_plates.saved_plates_id,
+    liked_store.liked_store_id,
+    saved_store.saved_store_id,
+    latest_price.liked_plates_id_count,
+    latest_price.saved_plates_id_count,
+    latest_price.reacts_count,
+    1::BIGINT AS rownumber,
+    COALESCE(latest_price.price < latest_price.previous_price, FALSE) AS price_dropped
+FROM public.plates_stats AS latest_price
+    INNER JOIN public.plates ON plates.plates_id = latest_price.plates_id
+    INNER JOIN public.users ON users.users_id = plates.users_id
+";
+
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    PriceMin(i32),
+    PriceMax(i32),
+    PlatesTypeIn(Vec<i32>),
+    ProvinceIn(Vec<i32>),
+    VehicleTypeIn(Vec<i32>),
+    Patterns(Vec<String>),
+    Hashtag(String),
+    TotalIn(Vec<i32>),
+    TotalMin(i32),
+    TotalMax(i32),
+    FrontNumber(i32),
+    BackNumber(i32),
+    BackNumberContains(i32),
+    BackNumberShape(BackNumberShape),
+    FrontTextPrefix(String),
+    FrontTextPrefixIn(Vec<String>),
+    FrontTextSimilar(Vec<String>),
+    SpecialFront,
+    Plates(i32),
+    Store(i32),
+    Pinned(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    AddDate,
+    PriceLowToHigh,
+    PriceHighToLow,
+    Reacts,
+    Random,
+}
+
+impl Sort {
+    pub fn parse(sort_by: &str) -> Self {
+        match sort_by {
+            "priceLowToHigh" => Sort::PriceLowToHigh,
+            "priceHighToLow" => Sort::PriceHighToLow,
+            "reacts" => Sort::Reacts,
+            "random" => Sort::Random,
+            _ => Sort::AddDate,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Sort::AddDate => "addDate",
+            Sort::PriceLowToHigh => "priceLowToHigh",
+            Sort::PriceHighToLow => "priceHighToLow",
+            Sort::Reacts => "reacts",
+            Sort::Random => "random",
+        }
+    }
+
+    // the sort key column, its cast when compared against a cursor and
+    // whether the listing runs ascending, random is keyed by the seeded hash
+    fn key(self) -> (&'static str, &'static str, bool) {
+        match self {
+            Sort::AddDate => ("plates.add_date", "TIMESTAMPTZ", false),
+            Sort::PriceLowToHigh => ("latest_price.price", "INTEGER", true),
+            Sort::PriceHighToLow => ("latest_price.price", "INTEGER", false),
+            Sort::Reacts => ("latest_price.reacts_count", "BIGINT", false),
+            Sort::Random => ("", "TEXT", true),
+        }
+    }
+
+    fn key_of(self, plates: &PlatesData) -> String {
+        match self {
+            Sort::AddDate => plates.add_date.clone(),
+            Sort::PriceLowToHigh | Sort::PriceHighToLow => plates.price.to_string(),
+            Sort::Reacts => plates.reacts_count.to_string(),
+            Sort::Random => String::new(),
+        }
+    }
+}
+
+// an opaque position in a listing: the sort key and plates_id of the last
+// row served, plus the seed of a random sort so later pages keep its order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub sort: String,
+    pub key: String,
+    pub id: i32,
+    pub seed: String,
+}
+
+pub fn invalid_cursor() -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        "invalid_cursor",
+        "cursor is not valid for this listing",
+    )
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        format!("{}\n{}\n{}\n{}", self.sort, self.key, self.id, self.seed)
+            .bytes()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, ApiError> {
+        if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+            return Err(invalid_cursor());
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid_cursor())?;
+        let text = String::from_utf8(bytes).map_err(|_| invalid_cursor())?;
+        let parts: Vec<&str> = text.split('\n').collect();
+        match parts[..] {
+            [sort, key, id, seed] => Ok(Cursor {
+                sort: sort.to_string(),
+                key: key.to_string(),
+                id: id.parse().map_err(|_| invalid_cursor())?,
+                seed: seed.to_string(),
+            }),
+            _ => Err(invalid_cursor()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlatesQuery {
+    users_id: i32,
+    predicates: Vec<Predicate>,
+    sort: Sort,
+    seed: String,
+    after: Option<Cursor>,
+    rank_by: Option<Vec<String>>,
+    limit: i32,
+    offset: i32,
+}
+
+fn prefix_patterns(candidates: &[String]) -> Vec<String> {
+    candidates
+        .iter()
+        .map(|candidate| format!("{}%", escape_like(candidate)))
+        .collect()
+}
+
+pub fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl PlatesQuery {
+    pub fn new(users_id: i32, sort_by: &str, limit: i32, offset: i32) -> Self {
+        PlatesQuery {
+            users_id,
+            predicates: Vec::new(),
+            sort: Sort::parse(sort_by),
+            seed: Uuid::new_v4().simple().to_string(),
+            after: None,
+            rank_by: None,
+            limit: limit.clamp(1, MAX_PAGE),
+            offset,
+        }
+    }
+
+    pub fn from_search(users_id: i32, search: &PlatesSearch) -> Result<Self, ApiError> {
+        let mut query = PlatesQuery::new(users_id, &search.sort_by, search.limit, search.offset);
+        if let Some(seed) = &search.seed {
+            query.seed = seed.clone();
+        }
+        if let Some(cursor) = &search.cursor {
+            query = query.after(Cursor::decode(cursor)?)?;
+        }
+        if let Some(price_min) = search.price_min {
+            query = query.and(Predicate::PriceMin(price_min));
+        }
+        if let Some(price_max) = search.price_max {
+            query = query.and(Predicate::PriceMax(price_max));
+        }
+        if let Some(list) = &search.plates_type_id_list {
+            query = query.and(Predicate::PlatesTypeIn(list.clone()));
+        }
+        if let Some(list) = &search.province_id_list {
+            query = query.and(Predicate::ProvinceIn(list.clone()));
+        }
+        if let Some(list) = &search.vehicle_type_id_list {
+            query = query.and(Predicate::VehicleTypeIn(list.clone()));
+        }
+        if let Some(list) = &search.pattern_list {
+            query = query.and(Predicate::Patterns(list.clone()));
+        }
+        if let Some(tag) = &search.hashtag {
+            query = query.and(Predicate::Hashtag(normalize_tag(tag)?));
+        }
+        if let Some(luck) = search.luck {
+            query = query.and(Predicate::TotalIn(luck_totals(luck)));
+        }
+        if let Some(total) = search.total {
+            query = query.and(Predicate::TotalIn(vec![total]));
+        }
+        if let Some(list) = &search.total_list {
+            query = query.and(Predicate::TotalIn(list.clone()));
+        }
+        if let Some(total) = search.total_min {
+            query = query.and(Predicate::TotalMin(total));
+        }
+        if let Some(total) = search.total_max {
+            query = query.and(Predicate::TotalMax(total));
+        }
+        if let Some(front_text) = &search.front_text {
+            query = query.and(Predicate::FrontTextPrefix(front_text.clone()));
+        }
+        if let Some(front_number) = search.front_number {
+            query = query.and(Predicate::FrontNumber(front_number));
+        }
+        if let Some(back_number) = search.back_number_contains {
+            query = query.and(Predicate::BackNumberContains(back_number));
+        }
+        if let Some(shape) = &search.back_number_shape {
+            query = query.and(Predicate::BackNumberShape(parse_shape(shape)?));
+        }
+        if search.special_front {
+            query = query.and(Predicate::SpecialFront);
+        }
+        Ok(query)
+    }
+
+    // continues the listing after the cursor instead of skipping offset rows
+    pub fn after(mut self, cursor: Cursor) -> Result<Self, ApiError> {
+        if cursor.sort != self.sort.name() {
+            return Err(invalid_cursor());
+        }
+        self.seed = cursor.seed.clone();
+        self.after = Some(cursor);
+        Ok(self)
+    }
+
+    // the cursor of the page following list, none once the listing is
+    // exhausted or when ranked by similarity which has no stable key
+    pub fn next_cursor(&self, list: &[PlatesData]) -> Option<String> {
+        if self.rank_by.is_some() || list.len() < self.limit.max(1) as usize {
+            return None;
+        }
+        list.last().map(|plates| {
+            Cursor {
+                sort: self.sort.name().to_string(),
+                key: self.sort.key_of(plates),
+                id: plates.plates_id,
+                seed: self.seed.clone(),
+            }
+            .encode()
+        })
+    }
+
+    pub fn and(mut self, predicate: Predicate) -> Self {
+        self.predicates.push(predicate);
+        self
+    }
+
+    // orders by the best trigram similarity of front_text to any candidate
+    // before the requested sort
+    pub fn rank_by(mut self, candidates: Vec<String>) -> Self {
+        self.rank_by = Some(candidates);
+        self
+    }
+
+    pub fn build(&self) -> QueryBuilder<'static, Postgres> {
+        let mut builder = QueryBuilder::new(LATEST_PRICE);
+        for join in [
+            "    LEFT JOIN public.liked_plates ON liked_plates.plates_id = plates.plates_id\n    AND liked_plates.users_id = ",
+            "\n    LEFT JOIN public.saved_plates ON saved_plates.plates_id = plates.plates_id\n    AND saved_plates.users_id = ",
+            "\n    LEFT JOIN public.liked_store ON liked_store.store_id = plates.users_id\n    AND liked_store.users_id = ",
+            "\n    LEFT JOIN public.saved_store ON saved_store.store_id = plates.users_id\n    AND saved_store.users_id = ",
+        ] {
+            builder.push(join);
+            builder.push_bind(self.users_id);
+        }
+        builder.push(
+            "
+WHERE plates.is_selling IS TRUE
+    AND plates.is_temporary IS NOT TRUE",
+        );
+        for predicate in &self.predicates {
+            builder.push("\n    AND ");
+            match predicate {
+                Predicate::PriceMin(price) => {
+                    builder.push("latest_price.price >= ");
+                    builder.push_bind(*price);
+                }
+                Predicate::PriceMax(price) => {
+                    builder.push("latest_price.price <= ");
+                    builder.push_bind(*price);
+                }
+                Predicate::PlatesTypeIn(list) => {
+                    builder.push("plates.plates_type_id = ANY(");
+                    builder.push_bind(list.clone());
+                    builder.push(")");
+                }
+                Predicate::ProvinceIn(list) => {
+                    builder.push("plates.province_id = ANY(");
+                    builder.push_bind(list.clone());
+                    builder.push(")");
+                }
+                Predicate::VehicleTypeIn(list) => {
+                    builder.push("plates.vehicle_type_id = ANY(");
+                    builder.push_bind(list.clone());
+                    builder.push(")");
+                }
+                Predicate::Patterns(list) => {
+                    builder.push("plates.plates_id IN (SELECT plates_pattern.plates_id FROM public.plates_pattern INNER JOIN public.pattern ON pattern.pattern_id = plates_pattern.pattern_id WHERE pattern.is_active IS TRUE AND pattern.name = ANY(");
+                    builder.push_bind(list.clone());
+                    builder.push("))");
+                }
+                Predicate::Hashtag(tag) => {
+                    builder.push("plates.plates_id IN (SELECT plates_hashtag.plates_id FROM public.plates_hashtag INNER JOIN public.hashtag ON hashtag.hashtag_id = plates_hashtag.hashtag_id WHERE hashtag.tag = ");
+                    builder.push_bind(tag.clone());
+                    builder.push(")");
+                }
+                Predicate::TotalIn(list) => {
+                    builder.push("plates.total = ANY(");
+                    builder.push_bind(list.clone());
+                    builder.push(")");
+                }
+                Predicate::TotalMin(total) => {
+                    builder.push("plates.total >= ");
+                    builder.push_bind(*total);
+                }
+                Predicate::TotalMax(total) => {
+                    builder.push("plates.total <= ");
+                    builder.push_bind(*total);
+                }
+                Predicate::FrontNumber(front_number) => {
+                    builder.push("plates.front_number = ");
+                    builder.push_bind(*front_number);
+                }
+                Predicate::BackNumber(back_number) => {
+                    builder.push("plates.back_number = ");
+                    builder.push_bind(*back_number);
+                }
+                Predicate::BackNumberContains(back_number) => {
+                    builder.push("CAST(plates.back_number AS text) LIKE ");
+                    builder.push_bind(format!("%{back_number}%"));
+                }
+                // letters and ? go through the indexed digit signature and
+                // literal digits through the back number text index
+                Predicate::BackNumberShape(shape) => match shape {
+                    BackNumberShape::Digits { like, .. } => {
+                        builder.push("plates.back_number_shape = ANY(");
+                        builder.push_bind(shape.signatures());
+                        builder.push(")");
+                        if like.chars().any(|c| c.is_ascii_digit()) {
+                            builder.push(" AND plates.back_number::TEXT LIKE ");
+                            builder.push_bind(like.clone());
+                        }
+                    }
+                    _ => {
+                        builder.push("plates.back_number = ANY(");
+                        builder.push_bind(shape.back_numbers());
+                        builder.push(")");
+                    }
+                },
+                Predicate::FrontTextPrefix(front_text) => {
+                    builder.push("plates.front_text LIKE ");
+                    builder.push_bind(format!("{}%", escape_like(front_text)));
+                }
+                Predicate::FrontTextPrefixIn(candidates) => {
+                    builder.push("plates.front_text LIKE ANY(");
+                    builder.push_bind(prefix_patterns(candidates));
+                    builder.push(")");
+                }
+                Predicate::FrontTextSimilar(candidates) => {
+                    builder
+                        .push("(SELECT MAX(similarity(plates.front_text, candidate)) FROM UNNEST(");
+                    builder.push_bind(candidates.clone());
+                    builder.push("::TEXT[]) AS candidate) >= ");
+                    builder.push_bind(SIMILARITY_THRESHOLD);
+                    builder.push(" AND NOT plates.front_text LIKE ANY(");
+                    builder.push_bind(prefix_patterns(candidates));
+                    builder.push(")");
+                }
+                Predicate::SpecialFront => {
+                    builder.push("plates.special_front_id != 1");
+                }
+                Predicate::Plates(plates_id) => {
+                    builder.push("plates.plates_id = ");
+                    builder.push_bind(*plates_id);
+                }
+                Predicate::Store(store_id) => {
+                    builder.push("plates.users_id = ");
+                    builder.push_bind(*store_id);
+                }
+                Predicate::Pinned(true) => {
+                    builder.push("plates.is_pin IS TRUE");
+                }
+                Predicate::Pinned(false) => {
+                    builder.push("plates.is_pin IS NOT TRUE");
+                }
+            }
+        }
+        let (column, cast, ascending) = self.sort.key();
+        let direction = if ascending { "ASC" } else { "DESC" };
+        if let (Some(cursor), None) = (&self.after, &self.rank_by) {
+            builder.push(format!("\n    AND ({column}"));
+            if self.sort == Sort::Random {
+                builder.push("MD5(");
+                builder.push_bind(self.seed.clone());
+                builder.push(" || plates.plates_id::TEXT)");
+            }
+            builder.push(format!(
+                ", plates.plates_id) {} (",
+                if ascending { ">" } else { "<" }
+            ));
+            if self.sort == Sort::Random {
+                builder.push("MD5(");
+                builder.push_bind(self.seed.clone());
+                builder.push(" || ");
+                builder.push_bind(cursor.id.to_string());
+                builder.push(")");
+            } else {
+                builder.push_bind(cursor.key.clone());
+                builder.push(format!("::{cast}"));
+            }
+            builder.push(", ");
+            builder.push_bind(cursor.id);
+            builder.push(")");
+        }
+        builder.push("\nORDER BY ");
+        if let Some(candidates) = &self.rank_by {
+            builder.push("(SELECT MAX(similarity(plates.front_text, candidate)) FROM UNNEST(");
+            builder.push_bind(candidates.clone());
+            builder.push("::TEXT[]) AS candidate) DESC, ");
+        }
+        if self.sort == Sort::Random {
+            builder.push("MD5(");
+            builder.push_bind(self.seed.clone());
+            builder.push(" || plates.plates_id::TEXT)");
+        } else {
+            builder.push(column);
+        }
+        builder.push(format!(
+            " {direction},\n    plates.plates_id {direction}\nLIMIT "
+        ));
+        builder.push_bind(self.limit);
+        if self.after.is_none() || self.rank_by.is_some() {
+            builder.push(" OFFSET ");
+            builder.push_bind(self.offset);
+        }
+        builder
+    }
+
+    pub async fn fetch_all(&self, pool: &Pool<Postgres>) -> Result<Vec<PlatesData>, sqlx::Error> {
+        let mut builder = self.build();
+        builder
+            .build_query_as::<PlatesData>()
+            .fetch_all(pool)
+            .await
+            .map(|list| {
+                list.into_iter()
+                    .map(PlatesData::with_plate_number)
+                    .collect()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE: [&str; 5] = [
+        "x'; DROP TABLE public.users; --",
+        "\" OR \"1\"=\"1",
+        "%_\\' UNION SELECT password FROM public.users --",
+        "กก') OR 1=1 --",
+        "$1; DELETE FROM public.plates",
+    ];
+
+    fn sql(search: &PlatesSearch) -> String {
+        PlatesQuery::from_search(1, search)
+            .unwrap()
+            .build()
+            .sql()
+            .to_string()
+    }
+
+    #[test]
+    fn escape_like_escapes_wildcards_and_backslash() {
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("a_b"), "a\\_b");
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+        assert_eq!(escape_like("%_\\"), "\\%\\_\\\\");
+        assert_eq!(escape_like("กก"), "กก");
+    }
+
+    #[test]
+    fn limit_is_clamped_to_a_page() {
+        assert_eq!(PlatesQuery::new(1, "addDate", 1_000_000, 0).limit, MAX_PAGE);
+        assert_eq!(PlatesQuery::new(1, "addDate", -1, 0).limit, 1);
+        assert_eq!(PlatesQuery::new(1, "addDate", 20, 0).limit, 20);
+    }
+
+    #[test]
+    fn escape_like_leaves_quotes_to_binding() {
+        assert_eq!(escape_like("'\"; --"), "'\"; --");
+    }
+
+    #[test]
+    fn hostile_text_is_bound_not_inlined() {
+        for hostile in HOSTILE {
+            let search = PlatesSearch {
+                front_text: Some(hostile.to_string()),
+                pattern_list: Some(vec![hostile.to_string()]),
+                seed: Some(hostile.to_string()),
+                ..PlatesSearch::default()
+            };
+            let sql = sql(&search);
+            assert!(!sql.contains(hostile), "{hostile} inlined into {sql}");
+            for word in ["DROP", "UNION", "DELETE", "1=1"] {
+                assert!(!sql.contains(word), "{word} inlined into {sql}");
+            }
+            assert!(sql.contains("plates.front_text LIKE $"));
+            assert!(sql.contains("pattern.name = ANY($"));
+        }
+    }
+
+    #[test]
+    fn hostile_sort_falls_back_to_add_date() {
+        for hostile in HOSTILE {
+            let search = PlatesSearch {
+                sort_by: hostile.to_string(),
+                ..PlatesSearch::default()
+            };
+            let sql = sql(&search);
+            assert!(!sql.contains(hostile));
+            assert!(sql.contains("ORDER BY plates.add_date DESC"));
+        }
+    }
+
+    #[test]
+    fn hostile_cursor_key_is_bound() {
+        for hostile in HOSTILE {
+            let cursor = Cursor {
+                sort: Sort::AddDate.name().to_string(),
+                key: hostile.to_string(),
+                id: 1,
+                seed: "seed".to_string(),
+            };
+            let search = PlatesSearch {
+                cursor: Some(cursor.encode()),
+                ..PlatesSearch::default()
+            };
+            let sql = sql(&search);
+            assert!(!sql.contains(hostile));
+            assert!(sql.contains("plates.plates_id) < ($"));
+        }
+    }
+
+    #[test]
+    fn hostile_structured_input_is_rejected() {
+        for hostile in HOSTILE {
+            let search = PlatesSearch {
+                back_number_shape: Some(hostile.to_string()),
+                ..PlatesSearch::default()
+            };
+            assert!(PlatesQuery::from_search(1, &search).is_err());
+            let search = PlatesSearch {
+                hashtag: Some(hostile.to_string()),
+                ..PlatesSearch::default()
+            };
+            assert!(PlatesQuery::from_search(1, &search).is_err());
+            let search = PlatesSearch {
+                cursor: Some(hostile.to_string()),
+                ..PlatesSearch::default()
+            };
+            assert!(PlatesQuery::from_search(1, &search).is_err());
+        }
+    }
+
+    #[test]
+    fn shape_uses_indexed_columns() {
+        let shape_sql = |shape: &str| {
+            sql(&PlatesSearch {
+                back_number_shape: Some(shape.to_string()),
+                ..PlatesSearch::default()
+            })
+        };
+        let sql = shape_sql("abab");
+        assert!(sql.contains("plates.back_number_shape = ANY($"));
+        assert!(!sql.contains("SUBSTR"));
+        assert!(!sql.contains("back_number::TEXT LIKE"));
+        assert!(shape_sql("x999").contains("plates.back_number::TEXT LIKE $"));
+        for shape in ["ascending", "descending", "palindrome"] {
+            let sql = shape_sql(shape);
+            assert!(sql.contains("plates.back_number = ANY($"));
+            assert!(!sql.contains("REVERSE") && !sql.contains("STRPOS"));
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{config::Config, error::ApiError};
+use axum::http::{HeaderMap, HeaderName};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use sqlx::{Pool, Postgres};
+use std::net::{IpAddr, SocketAddr};
+
+// a key is blocked once it counts more than max_attempts within window
+// minutes, the block starts at base_block seconds and doubles with every
+// further attempt up to max_block
+pub struct Limit {
+    pub name: &'static str,
+    pub max_attempts: i32,
+    pub window: i64,
+    pub base_block: i64,
+    pub max_block: i64,
+}
+
+pub const SIGN_IN_EMAIL: Limit = Limit {
+    name: "sign_in_email",
+    max_attempts: 5,
+    window: 60,
+    base_block: 30,
+    max_block: 3600,
+};
+pub const SIGN_IN_IP: Limit = Limit {
+    name: "sign_in_ip",
+    max_attempts: 20,
+    window: 60,
+    base_block: 30,
+    max_block: 3600,
+};
+pub const VERIFICATION_ID: Limit = Limit {
+    name: "verification_id",
+    max_attempts: 5,
+    window: 60,
+    base_block: 60,
+    max_block: 3600,
+};
+pub const VERIFICATION_IP: Limit = Limit {
+    name: "verification_ip",
+    max_attempts: 20,
+    window: 60,
+    base_block: 60,
+    max_block: 3600,
+};
+pub const SEND_CODE_EMAIL: Limit = Limit {
+    name: "send_code_email",
+    max_attempts: 3,
+    window: 60,
+    base_block: 60,
+    max_block: 86400,
+};
+pub const SEND_CODE_IP: Limit = Limit {
+    name: "send_code_ip",
+    max_attempts: 10,
+    window: 60,
+    base_block: 60,
+    max_block: 86400,
+};
+
+fn rate_key(limit: &Limit, value: &str) -> String {
+    format!("{}:{}", limit.name, value)
+}
+
+fn too_many_requests(retry_after: i64) -> ApiError {
+    ApiError {
+        retry_after: Some(retry_after.max(1) as u64),
+        ..ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too_many_attempts",
+            format!(
+                "too many attempts, retry after {} seconds",
+                retry_after.max(1)
+            ),
+        )
+    }
+}
+
+// counts the attempt against every key before it is evaluated and rejects
+// with 429 when a key is blocked or this attempt went over its limit, the
+// count and the block are read back from one upsert so concurrent attempts
+// cannot all pass a check made before any of them was counted
+pub async fn attempt(pool: &Pool<Postgres>, keys: &[(&Limit, &str)]) -> Result<(), ApiError> {
+    let now = Utc::now();
+    let mut retry_after = 0;
+    for (limit, value) in keys {
+        let upsert: Result<(i32, Option<DateTime<Utc>>), sqlx::Error> = sqlx::query_as("INSERT INTO public.rate_limit(key, attempts, window_start) VALUES ($1, 1, $2) ON CONFLICT (key) DO UPDATE SET attempts = CASE WHEN rate_limit.blocked_until > $2 THEN rate_limit.attempts WHEN rate_limit.window_start <= $3 THEN 1 ELSE rate_limit.attempts + 1 END, window_start = CASE WHEN rate_limit.blocked_until > $2 OR rate_limit.window_start > $3 THEN rate_limit.window_start ELSE $2 END RETURNING attempts, blocked_until")
+            .bind(rate_key(limit, value))
+            .bind(now)
+            .bind(now - Duration::minutes(limit.window))
+            .fetch_one(pool)
+            .await;
+        let (attempts, blocked_until) = match upsert {
+            Ok(ok) => ok,
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+        };
+        if let Some(blocked_until) = blocked_until.filter(|until| *until > now) {
+            let left = (blocked_until - now).num_milliseconds();
+            retry_after = retry_after.max((left + 999) / 1000);
+            continue;
+        }
+        if attempts <= limit.max_attempts {
+            continue;
+        }
+        let exponent = (attempts - limit.max_attempts - 1).min(30) as u32;
+        let block = limit
+            .base_block
+            .saturating_mul(2_i64.pow(exponent))
+            .min(limit.max_block);
+        let update = sqlx::query("UPDATE public.rate_limit SET blocked_until = GREATEST(blocked_until, $1) WHERE key = $2")
+            .bind(now + Duration::seconds(block))
+            .bind(rate_key(limit, value))
+            .execute(pool)
+            .await;
+        if update.is_err() {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+        retry_after = retry_after.max(block);
+    }
+    match retry_after {
+        0 => Ok(()),
+        _ => Err(too_many_requests(retry_after)),
+    }
+}
+
+// takes back a successful attempt from keys shared by many users, e.g. an ip,
+// so only failures count against them
+pub async fn refund(pool: &Pool<Postgres>, keys: &[(&Limit, &str)]) -> Result<(), ApiError> {
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|(limit, value)| rate_key(limit, value))
+        .collect();
+    let update = sqlx::query(
+        "UPDATE public.rate_limit SET attempts = GREATEST(attempts - 1, 0) WHERE key = ANY($1)",
+    )
+    .bind(&keys)
+    .execute(pool)
+    .await;
+    match update {
+        Ok(_) => Ok(()),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+// forgets the attempts of keys after a success
+pub async fn clear(pool: &Pool<Postgres>, keys: &[(&Limit, &str)]) -> Result<(), ApiError> {
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|(limit, value)| rate_key(limit, value))
+        .collect();
+    let delete = sqlx::query("DELETE FROM public.rate_limit WHERE key = ANY($1)")
+        .bind(&keys)
+        .execute(pool)
+        .await;
+    match delete {
+        Ok(_) => Ok(()),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+// the ip the per ip limits count against, the peer address unless the peer is
+// a trusted proxy
+pub fn client_ip(config: &Config, address: SocketAddr, headers: &HeaderMap) -> String {
+    forwarded_ip(
+        &config.trusted_proxies,
+        &config.client_ip_header,
+        address.ip().to_canonical(),
+        headers,
+    )
+    .to_string()
+}
+
+// proxies append the address they received from, so the right most address
+// that is not a trusted proxy is the first one a client could not have forged
+fn forwarded_ip(
+    trusted_proxies: &[IpAddr],
+    header: &HeaderName,
+    peer: IpAddr,
+    headers: &HeaderMap,
+) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all(header)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    let mut client = peer;
+    for ip in forwarded.iter().rev() {
+        match ip.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip.to_canonical();
+                if !trusted_proxies.contains(&client) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn client(peer: &str, headers: &HeaderMap) -> String {
+        let trusted_proxies: Vec<IpAddr> =
+            vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        forwarded_ip(
+            &trusted_proxies,
+            &HeaderName::from_static("x-forwarded-for"),
+            peer.parse().unwrap(),
+            headers,
+        )
+        .to_string()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        assert_eq!(
+            client("203.0.113.7", &forwarded(&["198.51.100.1"])),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn trusted_peer_forwards_the_client() {
+        assert_eq!(
+            client("10.0.0.1", &forwarded(&["198.51.100.1"])),
+            "198.51.100.1"
+        );
+        assert_eq!(
+            client("10.0.0.1", &forwarded(&["198.51.100.1, 10.0.0.2"])),
+            "198.51.100.1"
+        );
+        assert_eq!(
+            client("10.0.0.1", &forwarded(&["198.51.100.1", "10.0.0.2"])),
+            "198.51.100.1"
+        );
+    }
+
+    #[test]
+    fn forged_addresses_left_of_the_client_are_ignored() {
+        assert_eq!(
+            client("10.0.0.1", &forwarded(&["192.0.2.9, 198.51.100.1"])),
+            "198.51.100.1"
+        );
+    }
+
+    #[test]
+    fn trusted_peer_without_header_is_the_client() {
+        assert_eq!(client("10.0.0.1", &HeaderMap::new()), "10.0.0.1");
+        assert_eq!(client("10.0.0.1", &forwarded(&["unknown"])), "10.0.0.1");
+    }
+}
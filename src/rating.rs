@@ -1,13 +1,248 @@
+use crate::{
+    app_state::AppState, authentication::AuthUser, error::ApiError, plates::UniversalId,
+    query_builder::MAX_PAGE,
+};
+use axum::{extract::State, Extension, Json};
+use chrono::Utc;
+use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Rating {
     pub rating_id: i32,
     pub users_id: i32,
     pub store_id: i32,
-    pub score: f64,
+    pub score: i32,
     pub review: String,
     pub add_date: String,
+    pub edit_date: Option<String>,
+    pub reply: Option<String>,
+    pub reply_date: Option<String>,
+    pub name: String,
+    pub profile_uri: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewRating {
+    pub store_id: i32,
+    pub score: i32,
+    pub review: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RatingStore {
+    pub store_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RatingFilter {
+    pub store_id: i32,
+    pub limit: i32,
+    pub offset: i32,
 }
 
-// add_new_rating
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RatingReply {
+    pub rating_id: i32,
+    pub reply: String,
+}
+
+fn validate_rating(payload: &NewRating) -> Result<(), ApiError> {
+    if !(1..=5).contains(&payload.score) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_score",
+            "score must be between 1 and 5",
+        ));
+    }
+    Ok(())
+}
+
+// only users who received a plates from the store through an accepted transfer
+// can rate it
+pub async fn validate_buyer(
+    pool: &Pool<Postgres>,
+    users_id: i32,
+    store_id: i32,
+) -> Result<(), ApiError> {
+    let fetch: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("SELECT transfer_plates_id FROM public.transfer_plates WHERE (users_id = $1 AND store_id = $2 AND status = 'accepted') LIMIT 1")
+        .bind(store_id)
+        .bind(users_id)
+        .fetch_optional(pool)
+        .await;
+    match fetch {
+        Ok(ok) => match ok {
+            Some(_) => Ok(()),
+            None => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "not_buyer",
+                format!("users_id {users_id} has not bought from store_id {store_id}"),
+            )),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+pub async fn add_new_rating(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<NewRating>,
+) -> Result<Json<UniversalId>, ApiError> {
+    validate_rating(&payload)?;
+    if payload.store_id == users_id {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "self_rating",
+            "users cannot rate their own store",
+        ));
+    }
+    validate_buyer(&pool, users_id, payload.store_id).await?;
+    let insert: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("INSERT INTO public.rating(users_id, store_id, score, review, add_date) SELECT $1, users_id, $3, $4, $5 FROM public.users WHERE users_id = $2 ON CONFLICT (users_id, store_id) DO NOTHING RETURNING rating_id")
+        .bind(users_id)
+        .bind(payload.store_id)
+        .bind(payload.score)
+        .bind(payload.review.trim())
+        .bind(Utc::now())
+        .fetch_optional(&pool)
+        .await;
+    match insert {
+        Ok(ok) => match ok {
+            Some((rating_id,)) => Ok(Json(UniversalId { id: rating_id })),
+            None => {
+                let store: Result<Option<(i32,)>, sqlx::Error> =
+                    sqlx::query_as("SELECT users_id FROM public.users WHERE users_id = $1")
+                        .bind(payload.store_id)
+                        .fetch_optional(&pool)
+                        .await;
+                match store {
+                    Ok(Some(_)) => Err(ApiError::new(
+                        StatusCode::CONFLICT,
+                        "rating_exists",
+                        "store is already rated, edit the existing rating instead",
+                    )),
+                    Ok(None) => Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "users_not_found",
+                        format!("users {} does not exist", payload.store_id),
+                    )),
+                    Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+                }
+            }
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+pub async fn edit_rating(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<NewRating>,
+) -> Result<StatusCode, ApiError> {
+    validate_rating(&payload)?;
+    let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("UPDATE public.rating SET score = $1, review = $2, edit_date = $3 WHERE (users_id = $4 AND store_id = $5) RETURNING rating_id")
+        .bind(payload.score)
+        .bind(payload.review.trim())
+        .bind(Utc::now())
+        .bind(users_id)
+        .bind(payload.store_id)
+        .fetch_optional(&pool)
+        .await;
+    match update {
+        Ok(ok) => match ok {
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "rating_not_found",
+                "store has not been rated",
+            )),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+pub async fn delete_rating(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<RatingStore>,
+) -> Result<StatusCode, ApiError> {
+    let delete: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as(
+        "DELETE FROM public.rating WHERE (users_id = $1 AND store_id = $2) RETURNING rating_id",
+    )
+    .bind(users_id)
+    .bind(payload.store_id)
+    .fetch_optional(&pool)
+    .await;
+    match delete {
+        Ok(ok) => match ok {
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "rating_not_found",
+                "store has not been rated",
+            )),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+pub async fn query_rating(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<RatingFilter>,
+) -> Result<Json<Vec<Rating>>, StatusCode> {
+    let fetch: Result<Vec<Rating>, sqlx::Error> = sqlx::query_as("SELECT rating.rating_id, rating.users_id, rating.store_id, rating.score, rating.review, rating.add_date::TEXT, rating.edit_date::TEXT, rating.reply, rating.reply_date::TEXT, users.name, users.profile_uri FROM public.rating INNER JOIN public.users ON users.users_id = rating.users_id WHERE rating.store_id = $1 ORDER BY rating.add_date DESC LIMIT $2 OFFSET $3")
+        .bind(payload.store_id)
+        .bind(payload.limit.clamp(1, MAX_PAGE))
+        .bind(payload.offset)
+        .fetch_all(&pool)
+        .await;
+    match fetch {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+// only the rated store can reply, an empty reply removes it
+pub async fn reply_rating(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<RatingReply>,
+) -> Result<StatusCode, ApiError> {
+    let reply = payload.reply.trim();
+    let fetch: Result<Option<(i32,)>, sqlx::Error> =
+        sqlx::query_as("SELECT store_id FROM public.rating WHERE rating_id = $1")
+            .bind(payload.rating_id)
+            .fetch_optional(&pool)
+            .await;
+    match fetch {
+        Ok(Some((store_id,))) if store_id == users_id => (),
+        Ok(Some(_)) => {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "not_rated_store",
+                "only the rated store can reply",
+            ))
+        }
+        Ok(None) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "rating_not_found",
+                format!("rating {} does not exist", payload.rating_id),
+            ))
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+    let update = sqlx::query(
+        "UPDATE public.rating SET reply = $1, reply_date = $2 WHERE (rating_id = $3 AND store_id = $4)",
+    )
+    .bind((!reply.is_empty()).then_some(reply))
+    .bind((!reply.is_empty()).then(Utc::now))
+    .bind(payload.rating_id)
+    .bind(users_id)
+    .execute(&pool)
+    .await;
+    match update {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    app_state::AppState, authentication::AuthUser, error::ApiError, session::revoke_sessions,
+};
+use axum::{extract::State, Extension, Json};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+// ordered, a route that requires a role accepts every role above it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    VerifiedSeller,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "user" => Some(Role::User),
+            "verified_seller" => Some(Role::VerifiedSeller),
+            "moderator" => Some(Role::Moderator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::VerifiedSeller => "verified_seller",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsersRole {
+    pub users_id: i32,
+    pub role: Role,
+}
+
+// the role is carried in the access token, a promotion applies from the next
+// renew_token while a demotion revokes the sessions so it applies at once
+pub async fn edit_users_role(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<UsersRole>,
+) -> Result<StatusCode, ApiError> {
+    if payload.users_id == users_id {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "own_role",
+            "cannot change your own role",
+        ));
+    }
+    let update: Result<Option<(String,)>, sqlx::Error> = sqlx::query_as("UPDATE public.users SET role = $1 FROM public.users AS previous WHERE (users.users_id = $2 AND previous.users_id = users.users_id) RETURNING previous.role")
+        .bind(payload.role.name())
+        .bind(payload.users_id)
+        .fetch_optional(&pool)
+        .await;
+    let previous = match update {
+        Ok(ok) => match ok {
+            Some((role,)) => Role::parse(&role).unwrap_or_default(),
+            None => {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "users_not_found",
+                    format!("users {} does not exist", payload.users_id),
+                ))
+            }
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    if payload.role < previous
+        && revoke_sessions(&pool, payload.users_id, None)
+            .await
+            .is_err()
+    {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(StatusCode::OK)
+}
@@ -1,115 +1,178 @@
 use crate::{
     app_state::AppState,
-    constants::{BUCKET_NAME, COVER_KEY, PLATES_KEY, PROFILE_KEY},
+    authentication::AuthUser,
+    constants::{COVER_KEY, PLATES_KEY, PROFILE_KEY},
+    error::ApiError,
+    plates::validate_plates_owner,
 };
 use aws_sdk_s3::presigning::PresigningConfig;
-use axum::extract::{Query, State};
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
 use hyper::StatusCode;
+use sqlx::{Pool, Postgres};
 use std::{collections::HashMap, time::Duration};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ObjectKind {
+    Profile,
+    Cover,
+    Plates,
+}
+
+// what an object_key points at, id is the users_id of a profile or cover and
+// the plates_id of a plates image
+struct ObjectTarget {
+    kind: ObjectKind,
+    id: i32,
+    remove: bool,
+}
+
+fn invalid_object_key() -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        "invalid_object_key",
+        format!(
+            "object_key must be {PROFILE_KEY}, {COVER_KEY} or {PLATES_KEY} followed by /id/name"
+        ),
+    )
+}
+
+// object keys are {PROFILE_KEY}/{users_id}/name, {COVER_KEY}/{users_id}/name or
+// {PLATES_KEY}/{plates_id}/name and the id must belong to the caller, a bare
+// prefix removes the current object, plates take their plates_id from id then
+async fn object_target(
+    pool: &Pool<Postgres>,
+    params: &HashMap<String, String>,
+    users_id: i32,
+) -> Result<ObjectTarget, ApiError> {
+    let object_key = match params.get("object_key") {
+        Some(some) => some,
+        None => return Err(StatusCode::BAD_REQUEST.into()),
+    };
+    let mut parts = object_key.splitn(3, '/');
+    let kind = match parts.next() {
+        Some(PROFILE_KEY) => ObjectKind::Profile,
+        Some(COVER_KEY) => ObjectKind::Cover,
+        Some(PLATES_KEY) => ObjectKind::Plates,
+        _ => return Err(invalid_object_key()),
+    };
+    let (id, remove) = match (parts.next(), parts.next()) {
+        (None, _) => {
+            let id = match kind {
+                ObjectKind::Plates => match params.get("id").map(|id| id.parse::<i32>()) {
+                    Some(Ok(ok)) => ok,
+                    _ => return Err(StatusCode::BAD_REQUEST.into()),
+                },
+                _ => users_id,
+            };
+            (id, true)
+        }
+        (Some(id), Some(name)) if !name.is_empty() => match id.parse::<i32>() {
+            Ok(ok) => (ok, false),
+            Err(_) => return Err(invalid_object_key()),
+        },
+        _ => return Err(invalid_object_key()),
+    };
+    match kind {
+        ObjectKind::Plates => validate_plates_owner(pool, id, users_id).await?,
+        _ => {
+            if id != users_id {
+                return Err(ApiError::new(
+                    StatusCode::FORBIDDEN,
+                    "not_object_owner",
+                    format!("{object_key} does not belong to users_id {users_id}"),
+                ));
+            }
+        }
+    }
+    Ok(ObjectTarget { kind, id, remove })
+}
+
 pub async fn generate_presigned_url(
     Query(params): Query<HashMap<String, String>>,
-    State(AppState { pool: _, client }): State<AppState>,
-) -> Result<String, StatusCode> {
-    match params.get("object_key") {
-        Some(object_key) => {
-            let expires_in = Duration::from_secs(7200);
-            match PresigningConfig::expires_in(expires_in) {
-                Ok(presigning_config) => {
-                    let presigned_request = client
-                        .put_object()
-                        .bucket(BUCKET_NAME)
-                        .key(object_key)
-                        .presigned(presigning_config)
-                        .await;
-                    match presigned_request {
-                        Ok(ok) => Ok(ok.uri().to_string()),
-                        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-                    }
-                }
-                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    State(AppState {
+        pool,
+        client,
+        config,
+        ..
+    }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+) -> Result<String, ApiError> {
+    let target = object_target(&pool, &params, users_id).await?;
+    if target.remove {
+        return Err(invalid_object_key());
+    }
+    let expires_in = Duration::from_secs(7200);
+    match PresigningConfig::expires_in(expires_in) {
+        Ok(presigning_config) => {
+            let presigned_request = client
+                .put_object()
+                .bucket(&config.bucket_name)
+                .key(&params["object_key"])
+                .presigned(presigning_config)
+                .await;
+            match presigned_request {
+                Ok(ok) => Ok(ok.uri().to_string()),
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
             }
         }
-        None => Err(StatusCode::BAD_REQUEST),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
 
 pub async fn update_object(
     Query(params): Query<HashMap<String, String>>,
-    State(AppState { pool, client }): State<AppState>,
-) -> StatusCode {
-    let id = match params.get("id") {
-        Some(some) => match some.parse::<i32>() {
-            Ok(ok) => ok,
-            Err(_) => return StatusCode::BAD_REQUEST,
-        },
-        None => return StatusCode::BAD_REQUEST,
-    };
-    let object_key = match params.get("object_key") {
-        Some(some) => some.to_string(),
-        None => return StatusCode::BAD_REQUEST,
-    };
-
-    let sql = if object_key.contains(PROFILE_KEY) {
-        "SELECT profile_uri FROM public.users WHERE users_id = $1"
-    } else if object_key.contains(COVER_KEY) {
-        "SELECT cover_uri FROM public.users WHERE users_id = $1"
-    } else if object_key.contains(PLATES_KEY) {
-        "SELECT plates_uri FROM public.plates WHERE plates_id = $1"
-    } else {
-        return StatusCode::BAD_REQUEST;
+    State(AppState {
+        pool,
+        client,
+        config,
+        ..
+    }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+) -> Result<StatusCode, ApiError> {
+    let ObjectTarget { kind, id, remove } = object_target(&pool, &params, users_id).await?;
+    let sql = match kind {
+        ObjectKind::Profile => "SELECT profile_uri FROM public.users WHERE users_id = $1",
+        ObjectKind::Cover => "SELECT cover_uri FROM public.users WHERE users_id = $1",
+        ObjectKind::Plates => "SELECT plates_uri FROM public.plates WHERE plates_id = $1",
     };
     let fetch: Result<(Option<String>,), sqlx::Error> =
         sqlx::query_as(sql).bind(id).fetch_one(&pool).await;
-    let _ = match fetch {
-        Ok((ok,)) => match ok {
-            Some(key) => match client
+    match fetch {
+        Ok((Some(key),)) => {
+            let delete = client
                 .delete_object()
-                .bucket(BUCKET_NAME)
+                .bucket(&config.bucket_name)
                 .key(key)
                 .send()
-                .await
-            {
-                Ok(_) => (),
-                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-            },
-            None => (),
-        },
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-    };
-
-    if object_key == PROFILE_KEY || object_key == COVER_KEY || object_key == PLATES_KEY {
-        let sql = if object_key.contains(PROFILE_KEY) {
-            "UPDATE public.users SET profile_uri = null WHERE users_id = $1"
-        } else if object_key.contains(COVER_KEY) {
-            "UPDATE public.users SET cover_uri = null WHERE users_id = $1"
-        } else if object_key.contains(PLATES_KEY) {
-            "UPDATE public.plates SET plates_uri = null WHERE plates_id = $1"
-        } else {
-            return StatusCode::BAD_REQUEST;
-        };
-        match sqlx::query(sql).bind(id).execute(&pool).await {
-            Ok(_) => StatusCode::OK,
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                .await;
+            if delete.is_err() {
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
         }
+        Ok((None,)) => (),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+
+    let sql = match kind {
+        ObjectKind::Profile => "UPDATE public.users SET profile_uri = $1 WHERE users_id = $2",
+        ObjectKind::Cover => "UPDATE public.users SET cover_uri = $1 WHERE users_id = $2",
+        ObjectKind::Plates => "UPDATE public.plates SET plates_uri = $1 WHERE plates_id = $2",
+    };
+    let object_key = if remove {
+        None
     } else {
-        let sql = if object_key.contains(PROFILE_KEY) {
-            "UPDATE public.users SET profile_uri = $1 WHERE users_id = $2"
-        } else if object_key.contains(COVER_KEY) {
-            "UPDATE public.users SET cover_uri = $1 WHERE users_id = $2"
-        } else if object_key.contains(PLATES_KEY) {
-            "UPDATE public.plates SET plates_uri = $1 WHERE plates_id = $2"
-        } else {
-            return StatusCode::BAD_REQUEST;
-        };
-        match sqlx::query(sql)
-            .bind(object_key)
-            .bind(id)
-            .execute(&pool)
-            .await
-        {
-            Ok(_) => StatusCode::OK,
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        params.get("object_key")
+    };
+    match sqlx::query(sql)
+        .bind(object_key)
+        .bind(id)
+        .execute(&pool)
+        .await
+    {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
     }
 }
@@ -0,0 +1,250 @@
+use crate::{
+    app_state::AppState,
+    authentication::{AuthUser, Claims, Token},
+    config::Config,
+    constants::{EXP_DAY, EXP_MIN},
+    error::ApiError,
+    role::Role,
+};
+use axum::{extract::State, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AuthSession {
+    pub sessions_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Session {
+    pub sessions_id: i32,
+    pub user_agent: Option<String>,
+    pub add_date: String,
+    pub last_used: String,
+    pub expire_date: String,
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionId {
+    pub sessions_id: i32,
+}
+
+// the access and refresh token of a session share its id as sid and the
+// refresh token's jti, only the latest jti of a session can be renewed
+fn issue_tokens(
+    config: &Config,
+    users_id: i32,
+    sessions_id: i32,
+    jti: &str,
+    role: &str,
+    date: DateTime<Utc>,
+) -> Result<Token, StatusCode> {
+    let access_claims = Claims {
+        iat: date.timestamp() as usize,
+        exp: (date + Duration::minutes(EXP_MIN)).timestamp() as usize,
+        iss: config.issuer.clone(),
+        sub: users_id.to_string(),
+        sid: sessions_id,
+        jti: jti.to_string(),
+        role: Role::parse(role).unwrap_or_default(),
+    };
+    let refresh_claims = Claims {
+        exp: (date + Duration::days(EXP_DAY)).timestamp() as usize,
+        ..access_claims.clone()
+    };
+    let access_token = encode(
+        &Header::default(),
+        &access_claims,
+        &EncodingKey::from_secret(config.access_token_key.as_ref()),
+    );
+    let refresh_token = encode(
+        &Header::default(),
+        &refresh_claims,
+        &EncodingKey::from_secret(config.refresh_token_key.as_ref()),
+    );
+    match (access_token, refresh_token) {
+        (Ok(access_token), Ok(refresh_token)) => Ok(Token {
+            access_token,
+            refresh_token,
+        }),
+        _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn create_session(
+    pool: &Pool<Postgres>,
+    config: &Config,
+    users_id: i32,
+    user_agent: Option<&str>,
+) -> Result<Token, StatusCode> {
+    let date = Utc::now();
+    let jti = Uuid::new_v4().to_string();
+    let insert: Result<(i32, String), sqlx::Error> = sqlx::query_as("INSERT INTO public.sessions(users_id, jti, user_agent, add_date, last_used, expire_date) VALUES ($1, $2, $3, $4, $4, $5) RETURNING sessions_id, (SELECT role FROM public.users WHERE users_id = $1)")
+        .bind(users_id)
+        .bind(&jti)
+        .bind(user_agent)
+        .bind(date)
+        .bind(date + Duration::days(EXP_DAY))
+        .fetch_one(pool)
+        .await;
+    match insert {
+        Ok((sessions_id, role)) => issue_tokens(config, users_id, sessions_id, &jti, &role, date),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+// swaps the session's jti for a new one, presenting a jti that was already
+// rotated away means the refresh token leaked so the whole session is revoked
+pub async fn rotate_session(
+    pool: &Pool<Postgres>,
+    config: &Config,
+    claims: &Claims,
+) -> Result<Token, ApiError> {
+    let users_id = match claims.sub.parse::<i32>() {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::UNAUTHORIZED.into()),
+    };
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let fetch: Result<Option<(String, bool, String)>, sqlx::Error> = sqlx::query_as("SELECT sessions.jti, sessions.revoked IS TRUE OR sessions.expire_date <= NOW(), users.role FROM public.sessions INNER JOIN public.users ON users.users_id = sessions.users_id WHERE (sessions.sessions_id = $1 AND sessions.users_id = $2) FOR UPDATE OF sessions")
+        .bind(claims.sid)
+        .bind(users_id)
+        .fetch_optional(&mut *tx)
+        .await;
+    let role = match fetch {
+        Ok(Some((jti, false, role))) if jti == claims.jti => role,
+        Ok(Some((_, false, _))) => {
+            let revoke =
+                sqlx::query("UPDATE public.sessions SET revoked = true WHERE sessions_id = $1")
+                    .bind(claims.sid)
+                    .execute(&mut *tx)
+                    .await;
+            if revoke.is_err() || tx.commit().await.is_err() {
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+            return Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "refresh_token_reused",
+                "refresh token was already used, the session has been revoked",
+            ));
+        }
+        Ok(_) => return Err(StatusCode::UNAUTHORIZED.into()),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let date = Utc::now();
+    let jti = Uuid::new_v4().to_string();
+    let update = sqlx::query(
+        "UPDATE public.sessions SET jti = $1, last_used = $2, expire_date = $3 WHERE sessions_id = $4",
+    )
+    .bind(&jti)
+    .bind(date)
+    .bind(date + Duration::days(EXP_DAY))
+    .bind(claims.sid)
+    .execute(&mut *tx)
+    .await;
+    if update.is_err() || tx.commit().await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    issue_tokens(config, users_id, claims.sid, &jti, &role, date).map_err(ApiError::from)
+}
+
+pub async fn is_session_active(
+    pool: &Pool<Postgres>,
+    sessions_id: i32,
+    users_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let fetch: Option<(i32,)> = sqlx::query_as("SELECT sessions_id FROM public.sessions WHERE (sessions_id = $1 AND users_id = $2 AND revoked IS FALSE AND expire_date > NOW())")
+        .bind(sessions_id)
+        .bind(users_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(fetch.is_some())
+}
+
+// revokes every session of users_id other than keep
+pub async fn revoke_sessions(
+    pool: &Pool<Postgres>,
+    users_id: i32,
+    keep: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE public.sessions SET revoked = true WHERE (users_id = $1 AND sessions_id IS DISTINCT FROM $2 AND revoked IS FALSE)")
+        .bind(users_id)
+        .bind(keep)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+pub async fn logout(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Extension(AuthSession { sessions_id }): Extension<AuthSession>,
+) -> StatusCode {
+    let update = sqlx::query(
+        "UPDATE public.sessions SET revoked = true WHERE (sessions_id = $1 AND users_id = $2)",
+    )
+    .bind(sessions_id)
+    .bind(users_id)
+    .execute(&pool)
+    .await;
+    match update {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+pub async fn logout_all(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+) -> StatusCode {
+    match revoke_sessions(&pool, users_id, None).await {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+pub async fn revoke_session(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<SessionId>,
+) -> Result<StatusCode, ApiError> {
+    let update: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("UPDATE public.sessions SET revoked = true WHERE (sessions_id = $1 AND users_id = $2) RETURNING sessions_id")
+        .bind(payload.sessions_id)
+        .bind(users_id)
+        .fetch_optional(&pool)
+        .await;
+    match update {
+        Ok(ok) => match ok {
+            Some(_) => Ok(StatusCode::OK),
+            None => Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "session_not_found",
+                format!("session {} does not exist", payload.sessions_id),
+            )),
+        },
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+}
+
+pub async fn query_sessions(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Extension(AuthSession { sessions_id }): Extension<AuthSession>,
+) -> Result<Json<Vec<Session>>, StatusCode> {
+    let fetch: Result<Vec<Session>, sqlx::Error> = sqlx::query_as("SELECT sessions_id, user_agent, add_date::TEXT, last_used::TEXT, expire_date::TEXT, sessions_id = $2 AS current FROM public.sessions WHERE (users_id = $1 AND revoked IS FALSE AND expire_date > NOW()) ORDER BY last_used DESC")
+        .bind(users_id)
+        .bind(sessions_id)
+        .fetch_all(&pool)
+        .await;
+    match fetch {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
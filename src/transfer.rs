@@ -1,10 +1,16 @@
-use crate::app_state::AppState;
-use axum::{extract::State, Json};
-use chrono::Utc;
+use crate::{
+    app_state::AppState, authentication::AuthUser, error::ApiError, plates::UniversalId,
+    query_builder::MAX_PAGE,
+};
+use axum::{extract::State, Extension, Json};
+use chrono::{Duration, Utc};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
 
-#[derive(Debug, Serialize, Deserialize)]
+const TRANSFER_EXPIRE_DAY: i64 = 7;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Transfer {
     pub transfer_plates_id: i32,
     pub plates_id: i32,
@@ -12,61 +18,368 @@ pub struct Transfer {
     pub store_id: i32,
     pub add_date: String,
     pub received: bool,
-    pub received_date: String,
+    pub received_date: Option<String>,
+    pub status: String,
+    pub expire_date: String,
+    pub front_text: String,
+    pub front_number: i32,
+    pub back_number: i32,
+    pub province_id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewTransfer {
+    pub plates_id: i32,
+    pub store_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferId {
+    pub transfer_plates_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferFilter {
+    pub incoming: bool,
+    pub limit: i32,
+    pub offset: i32,
+}
+
+// plates_id, users_id, store_id, status, expired
+type TransferRow = (i32, i32, i32, String, bool);
+
+enum Side {
+    Sender,
+    Receiver,
+}
+
+async fn log_transfer(
+    tx: &mut Transaction<'static, Postgres>,
+    transfer_plates_id: i32,
+    users_id: i32,
+    action: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO public.transfer_plates_log(transfer_plates_id, users_id, action, add_date) VALUES ($1, $2, $3, $4)")
+        .bind(transfer_plates_id)
+        .bind(users_id)
+        .bind(action)
+        .bind(Utc::now())
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+}
+
+// locks a pending transfer the caller takes part in as side, an expired
+// transfer is closed and committed before GONE is returned
+async fn lock_pending_transfer(
+    mut tx: Transaction<'static, Postgres>,
+    transfer_plates_id: i32,
+    users_id: i32,
+    side: Side,
+) -> Result<(Transaction<'static, Postgres>, i32, i32, i32), ApiError> {
+    let fetch: Result<Option<TransferRow>, sqlx::Error> = sqlx::query_as("SELECT plates_id, users_id, store_id, status, expire_date <= NOW() FROM public.transfer_plates WHERE transfer_plates_id = $1 FOR UPDATE")
+        .bind(transfer_plates_id)
+        .fetch_optional(&mut *tx)
+        .await;
+    let (plates_id, sender_id, store_id, status, expired) = match fetch {
+        Ok(ok) => match ok {
+            Some(some) => some,
+            None => {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "transfer_not_found",
+                    format!("transfer {transfer_plates_id} does not exist"),
+                ))
+            }
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let party = match side {
+        Side::Sender => sender_id,
+        Side::Receiver => store_id,
+    };
+    if party != users_id {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "not_transfer_party",
+            "transfer belongs to another user",
+        ));
+    }
+    if status != "pending" {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "transfer_closed",
+            format!("transfer is already {status}"),
+        ));
+    }
+    if expired {
+        let update = sqlx::query(
+            "UPDATE public.transfer_plates SET status = 'expired' WHERE transfer_plates_id = $1",
+        )
+        .bind(transfer_plates_id)
+        .execute(&mut *tx)
+        .await;
+        if update.is_err()
+            || log_transfer(&mut tx, transfer_plates_id, users_id, "expire")
+                .await
+                .is_err()
+            || tx.commit().await.is_err()
+        {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+        return Err(ApiError::new(
+            StatusCode::GONE,
+            "transfer_expired",
+            "transfer has expired",
+        ));
+    }
+    Ok((tx, plates_id, sender_id, store_id))
+}
+
+async fn close_transfer(
+    mut tx: Transaction<'static, Postgres>,
+    transfer_plates_id: i32,
+    users_id: i32,
+    status: &str,
+    action: &str,
+) -> Result<StatusCode, ApiError> {
+    let update =
+        sqlx::query("UPDATE public.transfer_plates SET status = $1 WHERE transfer_plates_id = $2")
+            .bind(status)
+            .bind(transfer_plates_id)
+            .execute(&mut *tx)
+            .await;
+    if update.is_err()
+        || log_transfer(&mut tx, transfer_plates_id, users_id, action)
+            .await
+            .is_err()
+        || tx.commit().await.is_err()
+    {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(StatusCode::OK)
 }
 
 pub async fn transfer_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<Transfer>,
-) -> StatusCode {
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<NewTransfer>,
+) -> Result<Json<UniversalId>, ApiError> {
+    if payload.store_id == users_id {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "transfer_to_self",
+            "plates cannot be transferred to their owner",
+        ));
+    }
+    let mut tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let owner: Result<Option<(i32,)>, sqlx::Error> =
+        sqlx::query_as("SELECT users_id FROM public.plates WHERE plates_id = $1 FOR UPDATE")
+            .bind(payload.plates_id)
+            .fetch_optional(&mut *tx)
+            .await;
+    match owner {
+        Ok(Some((owner_id,))) if owner_id == users_id => (),
+        Ok(Some(_)) => {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "not_plates_owner",
+                format!("plates {} belongs to another user", payload.plates_id),
+            ))
+        }
+        Ok(None) => {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                "plates_not_found",
+                format!("plates {} does not exist", payload.plates_id),
+            ))
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+    let receiver: Result<Option<(i32,)>, sqlx::Error> =
+        sqlx::query_as("SELECT users_id FROM public.users WHERE users_id = $1")
+            .bind(payload.store_id)
+            .fetch_optional(&mut *tx)
+            .await;
+    match receiver {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "users_not_found",
+                format!("users {} does not exist", payload.store_id),
+            ))
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+    let expire: Result<Vec<(i32,)>, sqlx::Error> = sqlx::query_as("UPDATE public.transfer_plates SET status = 'expired' WHERE plates_id = $1 AND status = 'pending' AND expire_date <= NOW() RETURNING transfer_plates_id")
+        .bind(payload.plates_id)
+        .fetch_all(&mut *tx)
+        .await;
+    match expire {
+        Ok(ok) => {
+            for (transfer_plates_id,) in ok {
+                if log_transfer(&mut tx, transfer_plates_id, users_id, "expire")
+                    .await
+                    .is_err()
+                {
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+    let pending: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("SELECT transfer_plates_id FROM public.transfer_plates WHERE plates_id = $1 AND status = 'pending'")
+        .bind(payload.plates_id)
+        .fetch_optional(&mut *tx)
+        .await;
+    match pending {
+        Ok(None) => (),
+        Ok(Some(_)) => {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "transfer_pending",
+                format!(
+                    "plates {} already has a pending transfer",
+                    payload.plates_id
+                ),
+            ))
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
     let add_date = Utc::now();
-    let insert = sqlx::query("INSERT INTO public.transfer_plates(plates_id, users_id, store_id, add_date) VALUES ($1, $2, $3, $4)")
+    let insert: Result<(i32,), sqlx::Error> = sqlx::query_as("INSERT INTO public.transfer_plates(plates_id, users_id, store_id, add_date, received, status, expire_date) VALUES ($1, $2, $3, $4, false, 'pending', $5) RETURNING transfer_plates_id")
         .bind(payload.plates_id)
-        .bind(payload.users_id)
+        .bind(users_id)
         .bind(payload.store_id)
         .bind(add_date)
-        .fetch_optional(&pool)
+        .bind(add_date + Duration::days(TRANSFER_EXPIRE_DAY))
+        .fetch_one(&mut *tx)
         .await;
-    match insert {
-        Ok(ok) => match ok {
-            Some(_) => StatusCode::OK,
-            None => StatusCode::BAD_REQUEST,
-        },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    let transfer_plates_id = match insert {
+        Ok((transfer_plates_id,)) => transfer_plates_id,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    if log_transfer(&mut tx, transfer_plates_id, users_id, "initiate")
+        .await
+        .is_err()
+        || tx.commit().await.is_err()
+    {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(Json(UniversalId {
+        id: transfer_plates_id,
+    }))
+}
+
+pub async fn query_transfer_plates(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<TransferFilter>,
+) -> Result<Json<Vec<Transfer>>, StatusCode> {
+    let fetch: Result<Vec<Transfer>, sqlx::Error> = sqlx::query_as("SELECT transfer_plates.transfer_plates_id, transfer_plates.plates_id, transfer_plates.users_id, transfer_plates.store_id, transfer_plates.add_date::TEXT, transfer_plates.received, transfer_plates.received_date::TEXT, CASE WHEN transfer_plates.status = 'pending' AND transfer_plates.expire_date <= NOW() THEN 'expired' ELSE transfer_plates.status END AS status, transfer_plates.expire_date::TEXT, plates.front_text, plates.front_number, plates.back_number, plates.province_id, users.name FROM public.transfer_plates INNER JOIN public.plates ON plates.plates_id = transfer_plates.plates_id INNER JOIN public.users ON users.users_id = (CASE WHEN $2 THEN transfer_plates.users_id ELSE transfer_plates.store_id END) WHERE (CASE WHEN $2 THEN transfer_plates.store_id ELSE transfer_plates.users_id END) = $1 ORDER BY transfer_plates.add_date DESC LIMIT $3 OFFSET $4")
+        .bind(users_id)
+        .bind(payload.incoming)
+        .bind(payload.limit.clamp(1, MAX_PAGE))
+        .bind(payload.offset)
+        .fetch_all(&pool)
+        .await;
+    match fetch {
+        Ok(ok) => Ok(Json(ok)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
 pub async fn accept_plates(
-    State(AppState { pool, client: _ }): State<AppState>,
-    Json(payload): Json<Transfer>,
-) -> StatusCode {
-    let received_date = Utc::now();
-    let update: Result<Option<(i32, i32)>, sqlx::Error> = sqlx::query_as("UPDATE public.transfer_plates SET received = true, received_date = $1 WHERE transfer_plates_id = $2 RETURNING plates_id, store_id")
-        .bind(received_date)
-        .bind(payload.transfer_plates_id)
-        .fetch_optional(&pool)
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<TransferId>,
+) -> Result<StatusCode, ApiError> {
+    let tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let (mut tx, plates_id, sender_id, store_id) =
+        lock_pending_transfer(tx, payload.transfer_plates_id, users_id, Side::Receiver).await?;
+    let update_users_id: Result<Option<(i32,)>, sqlx::Error> = sqlx::query_as("UPDATE public.plates SET users_id = $1, is_pin = false WHERE plates_id = $2 AND users_id = $3 RETURNING plates_id")
+        .bind(store_id)
+        .bind(plates_id)
+        .bind(sender_id)
+        .fetch_optional(&mut *tx)
         .await;
-    match update {
-        Ok(ok) => match ok {
-            Some((plates_id, users_id)) => {
-                let update_users_id =
-                    sqlx::query("UPDATE public.plates SET users_id = $1 WHERE plates_id = $2")
-                        .bind(users_id)
-                        .bind(plates_id)
-                        .fetch_optional(&pool)
-                        .await;
-                match update_users_id {
-                    Ok(ok) => match ok {
-                        Some(_) => StatusCode::OK,
-                        None => StatusCode::BAD_REQUEST,
-                    },
-                    Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
-                }
-            }
-            None => StatusCode::BAD_REQUEST,
-        },
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    match update_users_id {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "plates_owner_changed",
+                "sender no longer owns the plates",
+            ))
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    }
+    let received = sqlx::query(
+        "UPDATE public.transfer_plates SET received = true, received_date = $1 WHERE transfer_plates_id = $2",
+    )
+    .bind(Utc::now())
+    .bind(payload.transfer_plates_id)
+    .execute(&mut *tx)
+    .await;
+    if received.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
     }
+    close_transfer(
+        tx,
+        payload.transfer_plates_id,
+        users_id,
+        "accepted",
+        "accept",
+    )
+    .await
 }
 
-// query_transfer_plates
+pub async fn reject_plates(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<TransferId>,
+) -> Result<StatusCode, ApiError> {
+    let tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let (tx, ..) =
+        lock_pending_transfer(tx, payload.transfer_plates_id, users_id, Side::Receiver).await?;
+    close_transfer(
+        tx,
+        payload.transfer_plates_id,
+        users_id,
+        "rejected",
+        "reject",
+    )
+    .await
+}
+
+pub async fn cancel_transfer_plates(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(AuthUser { users_id }): Extension<AuthUser>,
+    Json(payload): Json<TransferId>,
+) -> Result<StatusCode, ApiError> {
+    let tx = match pool.begin().await {
+        Ok(ok) => ok,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR.into()),
+    };
+    let (tx, ..) =
+        lock_pending_transfer(tx, payload.transfer_plates_id, users_id, Side::Sender).await?;
+    close_transfer(
+        tx,
+        payload.transfer_plates_id,
+        users_id,
+        "cancelled",
+        "cancel",
+    )
+    .await
+}
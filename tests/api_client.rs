@@ -0,0 +1,58 @@
+// runs against a throwaway database per test created by sqlx::test from
+// DATABASE_URL, e.g. DATABASE_URL=postgres://postgres@localhost/postgres
+use app_789plates_server::api_client::{create_client, flush_usage, hash_key, use_key, ApiKeys};
+use sqlx::PgPool;
+
+async fn usage(pool: &PgPool) -> (i64, bool) {
+    let (request_count, used): (i64, bool) =
+        sqlx::query_as("SELECT request_count, last_used IS NOT NULL FROM public.api_keys")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+    (request_count, used)
+}
+
+#[sqlx::test]
+async fn requests_are_counted_in_memory_until_flushed(pool: PgPool) {
+    let api_keys = ApiKeys::default();
+    let key = create_client(&pool, "app").await.unwrap().unwrap();
+    for _ in 0..3 {
+        assert!(use_key(&pool, &api_keys, &key).await.unwrap().is_some());
+    }
+    assert_eq!(usage(&pool).await, (0, false));
+    flush_usage(&pool, &api_keys).await.unwrap();
+    assert_eq!(usage(&pool).await, (3, true));
+    // nothing counted since the last flush adds nothing
+    flush_usage(&pool, &api_keys).await.unwrap();
+    assert_eq!(usage(&pool).await, (3, true));
+}
+
+#[sqlx::test]
+async fn unknown_key_is_refused(pool: PgPool) {
+    let api_keys = ApiKeys::default();
+    create_client(&pool, "app").await.unwrap();
+    assert!(use_key(&pool, &api_keys, "unknown")
+        .await
+        .unwrap()
+        .is_none());
+    flush_usage(&pool, &api_keys).await.unwrap();
+    assert_eq!(usage(&pool).await, (0, false));
+}
+
+#[sqlx::test]
+async fn unknown_key_is_not_looked_up_again(pool: PgPool) {
+    let api_keys = ApiKeys::default();
+    let key = create_client(&pool, "app").await.unwrap().unwrap();
+    sqlx::query("UPDATE public.api_keys SET key_hash = 'hidden'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(use_key(&pool, &api_keys, &key).await.unwrap().is_none());
+    // still refused from the cache once the key is back in the database
+    sqlx::query("UPDATE public.api_keys SET key_hash = $1")
+        .bind(hash_key(&key))
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(use_key(&pool, &api_keys, &key).await.unwrap().is_none());
+}
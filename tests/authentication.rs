@@ -0,0 +1,129 @@
+// runs against a throwaway database per test created by sqlx::test from
+// DATABASE_URL, e.g. DATABASE_URL=postgres://postgres@localhost/postgres
+use app_789plates_server::{
+    api_client::ApiKeys,
+    app_state::AppState,
+    authentication::{reset_password, Authentication},
+    config::Config,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderName},
+    Json,
+};
+use hyper::StatusCode;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+fn app_state(pool: PgPool) -> AppState {
+    let config = Config {
+        database_url: String::new(),
+        bind_address: "127.0.0.1:0".parse().unwrap(),
+        access_token_key: "access".to_string(),
+        refresh_token_key: "refresh".to_string(),
+        issuer: "test".to_string(),
+        public_url: "http://localhost".to_string(),
+        smtp_relay: String::new(),
+        smtp_email: String::new(),
+        smtp_password: String::new(),
+        aws_access_key_id: None,
+        aws_secret_access_key: None,
+        aws_region: "us-east-1".to_string(),
+        bucket_name: String::new(),
+        trusted_proxies: Vec::new(),
+        client_ip_header: HeaderName::from_static("x-forwarded-for"),
+    };
+    let client = aws_sdk_s3::Client::from_conf(
+        aws_sdk_s3::Config::builder()
+            .behavior_version_latest()
+            .build(),
+    );
+    AppState {
+        pool,
+        client,
+        config: Arc::new(config),
+        api_keys: Arc::new(ApiKeys::default()),
+    }
+}
+
+async fn insert_users(pool: &PgPool, email: &str) {
+    sqlx::query("INSERT INTO public.users (name, email, password) VALUES ($1, $1, 'unchanged')")
+        .bind(email)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+// a code that was already sent to email and validated
+async fn insert_verified(pool: &PgPool, email: &str) -> i32 {
+    let (verification_id,): (i32,) = sqlx::query_as("INSERT INTO public.verification (reference, code, expire, verified, email) VALUES (1, 123456, NOW() + INTERVAL '5 minutes', TRUE, $1) RETURNING verification_id")
+        .bind(email)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    verification_id
+}
+
+fn reset(verification_id: i32, email: &str) -> Json<Authentication> {
+    Json(Authentication {
+        verification_id,
+        reference: 1,
+        code: 123456,
+        email: email.to_string(),
+        password: "new password".to_string(),
+        access_token: String::new(),
+        refresh_token: String::new(),
+        users_id: 0,
+    })
+}
+
+async fn password(pool: &PgPool, email: &str) -> String {
+    let (password,): (String,) =
+        sqlx::query_as("SELECT password FROM public.users WHERE email = $1")
+            .bind(email)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+    password
+}
+
+#[sqlx::test]
+async fn code_for_one_email_cannot_reset_another(pool: PgPool) {
+    insert_users(&pool, "a@example.com").await;
+    insert_users(&pool, "b@example.com").await;
+    let verification_id = insert_verified(&pool, "a@example.com").await;
+    let reset_b = reset_password(
+        State(app_state(pool.clone())),
+        HeaderMap::new(),
+        reset(verification_id, "b@example.com"),
+    )
+    .await;
+    assert_eq!(reset_b.err(), Some(StatusCode::BAD_REQUEST));
+    assert_eq!(password(&pool, "b@example.com").await, "unchanged");
+    let (sessions,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM public.sessions")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions, 0);
+}
+
+#[sqlx::test]
+async fn code_resets_its_own_email_once(pool: PgPool) {
+    insert_users(&pool, "a@example.com").await;
+    let verification_id = insert_verified(&pool, "a@example.com").await;
+    let first = reset_password(
+        State(app_state(pool.clone())),
+        HeaderMap::new(),
+        reset(verification_id, "a@example.com"),
+    )
+    .await;
+    assert!(first.is_ok());
+    assert_ne!(password(&pool, "a@example.com").await, "unchanged");
+    let second = reset_password(
+        State(app_state(pool.clone())),
+        HeaderMap::new(),
+        reset(verification_id, "a@example.com"),
+    )
+    .await;
+    assert_eq!(second.err(), Some(StatusCode::BAD_REQUEST));
+}
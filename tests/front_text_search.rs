@@ -0,0 +1,79 @@
+// runs against a throwaway database per test created by sqlx::test from
+// DATABASE_URL, e.g. DATABASE_URL=postgres://postgres@localhost/postgres
+use app_789plates_server::query::{fetch_plates_group, PlatesGroup, PlatesSearch};
+use sqlx::PgPool;
+
+async fn insert_users(pool: &PgPool, email: &str) -> i32 {
+    let (users_id,): (i32,) = sqlx::query_as(
+        "INSERT INTO public.users(name, email, password) VALUES ($1, $1, '') RETURNING users_id",
+    )
+    .bind(email)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    users_id
+}
+
+// a selling plates with a price, listings only show those
+async fn insert_plates(pool: &PgPool, users_id: i32, front_text: &str, back_number: i32) -> i32 {
+    let (plates_id,): (i32,) = sqlx::query_as("INSERT INTO public.plates(front_text, front_number, back_number, province_id, plates_type_id, vehicle_type_id, users_id, is_selling) VALUES ($1, 1, $2, 1, 1, 1, $3, TRUE) RETURNING plates_id")
+        .bind(front_text)
+        .bind(back_number)
+        .bind(users_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO public.plates_stats(plates_id, price) VALUES ($1, 1000)")
+        .bind(plates_id)
+        .execute(pool)
+        .await
+        .unwrap();
+    plates_id
+}
+
+fn plates_id_list(group: &PlatesGroup) -> (Vec<i32>, Vec<i32>) {
+    let mut exact: Vec<i32> = group.exact.iter().map(|plates| plates.plates_id).collect();
+    let mut suggestion: Vec<i32> = group
+        .suggestion
+        .iter()
+        .map(|plates| plates.plates_id)
+        .collect();
+    exact.sort();
+    suggestion.sort();
+    (exact, suggestion)
+}
+
+#[sqlx::test]
+async fn empty_front_text_is_no_filter(pool: PgPool) {
+    let users_id = insert_users(&pool, "seller@example.com").await;
+    let exact = insert_plates(&pool, users_id, "กก", 99).await;
+    let contains = insert_plates(&pool, users_id, "ขข", 199).await;
+    insert_plates(&pool, users_id, "คค", 1234).await;
+    let search = PlatesSearch {
+        front_text_fuzzy: Some(" ".to_string()),
+        back_number: Some(99),
+        ..PlatesSearch::default()
+    };
+    let group = fetch_plates_group(&pool, users_id, &search).await.unwrap();
+    let (exact_list, suggestion_list) = plates_id_list(&group);
+    assert_eq!(exact_list, vec![exact]);
+    assert!(suggestion_list.contains(&contains));
+}
+
+#[sqlx::test]
+async fn front_text_with_back_number_suggests_contains(pool: PgPool) {
+    let users_id = insert_users(&pool, "seller@example.com").await;
+    let exact = insert_plates(&pool, users_id, "กก", 99).await;
+    let contains = insert_plates(&pool, users_id, "กก", 199).await;
+    let other_text = insert_plates(&pool, users_id, "มม", 199).await;
+    let search = PlatesSearch {
+        front_text_fuzzy: Some("kk".to_string()),
+        back_number: Some(99),
+        ..PlatesSearch::default()
+    };
+    let group = fetch_plates_group(&pool, users_id, &search).await.unwrap();
+    let (exact_list, suggestion_list) = plates_id_list(&group);
+    assert_eq!(exact_list, vec![exact]);
+    assert!(suggestion_list.contains(&contains));
+    assert!(!suggestion_list.contains(&other_text));
+}
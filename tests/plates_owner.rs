@@ -0,0 +1,52 @@
+// runs against a throwaway database per test created by sqlx::test from
+// DATABASE_URL, e.g. DATABASE_URL=postgres://postgres@localhost/postgres
+use app_789plates_server::plates::validate_plates_owner;
+use hyper::StatusCode;
+use sqlx::PgPool;
+
+async fn insert_users(pool: &PgPool, email: &str) -> i32 {
+    let (users_id,): (i32,) = sqlx::query_as(
+        "INSERT INTO public.users(name, email, password) VALUES ($1, $1, '') RETURNING users_id",
+    )
+    .bind(email)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    users_id
+}
+
+async fn insert_plates(pool: &PgPool, users_id: i32) -> i32 {
+    let (plates_id,): (i32,) = sqlx::query_as("INSERT INTO public.plates(front_text, front_number, back_number, province_id, plates_type_id, vehicle_type_id, users_id) VALUES ('กก', 1, 1234, 1, 1, 1, $1) RETURNING plates_id")
+        .bind(users_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    plates_id
+}
+
+#[sqlx::test]
+async fn owner_passes(pool: PgPool) {
+    let owner = insert_users(&pool, "owner@example.com").await;
+    let plates_id = insert_plates(&pool, owner).await;
+    assert!(validate_plates_owner(&pool, plates_id, owner).await.is_ok());
+}
+
+#[sqlx::test]
+async fn non_owner_is_forbidden(pool: PgPool) {
+    let owner = insert_users(&pool, "owner@example.com").await;
+    let other = insert_users(&pool, "other@example.com").await;
+    let plates_id = insert_plates(&pool, owner).await;
+    let err = validate_plates_owner(&pool, plates_id, other)
+        .await
+        .unwrap_err();
+    assert_eq!(err.status, StatusCode::FORBIDDEN);
+    assert_eq!(err.error, "not_plates_owner");
+}
+
+#[sqlx::test]
+async fn missing_plates_is_not_found(pool: PgPool) {
+    let owner = insert_users(&pool, "owner@example.com").await;
+    let err = validate_plates_owner(&pool, 1, owner).await.unwrap_err();
+    assert_eq!(err.status, StatusCode::NOT_FOUND);
+    assert_eq!(err.error, "plates_not_found");
+}
@@ -0,0 +1,59 @@
+// runs against a throwaway database per test created by sqlx::test from
+// DATABASE_URL, e.g. DATABASE_URL=postgres://postgres@localhost/postgres
+use app_789plates_server::rate_limit::{self, Limit};
+use hyper::StatusCode;
+use sqlx::PgPool;
+
+const LIMIT: Limit = Limit {
+    name: "test",
+    max_attempts: 5,
+    window: 60,
+    base_block: 60,
+    max_block: 3600,
+};
+
+#[sqlx::test]
+async fn concurrent_attempts_pass_at_most_max_attempts(pool: PgPool) {
+    let attempts: Vec<_> = (0..20)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move { rate_limit::attempt(&pool, &[(&LIMIT, "key")]).await })
+        })
+        .collect();
+    let mut passed = 0;
+    for attempt in attempts {
+        match attempt.await.unwrap() {
+            Ok(()) => passed += 1,
+            Err(err) => {
+                assert_eq!(err.status, StatusCode::TOO_MANY_REQUESTS);
+                assert!(err.retry_after.is_some());
+            }
+        }
+    }
+    assert_eq!(passed, LIMIT.max_attempts);
+}
+
+#[sqlx::test]
+async fn blocked_key_is_rejected_until_cleared(pool: PgPool) {
+    let keys = [(&LIMIT, "key")];
+    for _ in 0..LIMIT.max_attempts {
+        rate_limit::attempt(&pool, &keys).await.unwrap();
+    }
+    let err = rate_limit::attempt(&pool, &keys).await.unwrap_err();
+    assert_eq!(err.retry_after, Some(LIMIT.base_block as u64));
+    // attempts while blocked do not extend the block
+    let err = rate_limit::attempt(&pool, &keys).await.unwrap_err();
+    assert!(err.retry_after.unwrap() <= LIMIT.base_block as u64);
+    rate_limit::clear(&pool, &keys).await.unwrap();
+    assert!(rate_limit::attempt(&pool, &keys).await.is_ok());
+}
+
+#[sqlx::test]
+async fn refund_only_counts_failures(pool: PgPool) {
+    let keys = [(&LIMIT, "ip")];
+    for _ in 0..LIMIT.max_attempts * 2 {
+        rate_limit::attempt(&pool, &keys).await.unwrap();
+        rate_limit::refund(&pool, &keys).await.unwrap();
+    }
+    assert!(rate_limit::attempt(&pool, &keys).await.is_ok());
+}
@@ -0,0 +1,55 @@
+// runs against a throwaway database per test created by sqlx::test from
+// DATABASE_URL, e.g. DATABASE_URL=postgres://postgres@localhost/postgres
+use app_789plates_server::rating::validate_buyer;
+use hyper::StatusCode;
+use sqlx::PgPool;
+
+async fn insert_users(pool: &PgPool, email: &str) -> i32 {
+    let (users_id,): (i32,) = sqlx::query_as(
+        "INSERT INTO public.users(name, email, password) VALUES ($1, $1, '') RETURNING users_id",
+    )
+    .bind(email)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    users_id
+}
+
+// store sends a plates to buyer, the transfer is left with status
+async fn insert_transfer(pool: &PgPool, store: i32, buyer: i32, status: &str) {
+    let (plates_id,): (i32,) = sqlx::query_as("INSERT INTO public.plates(front_text, front_number, back_number, province_id, plates_type_id, vehicle_type_id, users_id) VALUES ('กก', (SELECT COUNT(*) + 1 FROM public.plates), 1234, 1, 1, 1, $1) RETURNING plates_id")
+        .bind(store)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO public.transfer_plates(plates_id, users_id, store_id, status) VALUES ($1, $2, $3, $4)")
+        .bind(plates_id)
+        .bind(store)
+        .bind(buyer)
+        .bind(status)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+#[sqlx::test]
+async fn buyer_passes(pool: PgPool) {
+    let store = insert_users(&pool, "store@example.com").await;
+    let buyer = insert_users(&pool, "buyer@example.com").await;
+    insert_transfer(&pool, store, buyer, "accepted").await;
+    assert!(validate_buyer(&pool, buyer, store).await.is_ok());
+}
+
+#[sqlx::test]
+async fn non_buyer_is_forbidden(pool: PgPool) {
+    let store = insert_users(&pool, "store@example.com").await;
+    let buyer = insert_users(&pool, "buyer@example.com").await;
+    let other = insert_users(&pool, "other@example.com").await;
+    insert_transfer(&pool, store, buyer, "pending").await;
+    insert_transfer(&pool, buyer, store, "accepted").await;
+    for users_id in [buyer, other] {
+        let err = validate_buyer(&pool, users_id, store).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::FORBIDDEN);
+        assert_eq!(err.error, "not_buyer");
+    }
+}